@@ -8,7 +8,7 @@ pub struct AutoVec<T> {
     vec: Vec<T>,
 }
 
-impl<T: Clone + Copy> AutoVec<T> {
+impl<T: Clone> AutoVec<T> {
     pub fn new(fill: T) -> Self {
         Self {
             fill,
@@ -17,25 +17,54 @@ impl<T: Clone + Copy> AutoVec<T> {
     }
 
     pub fn set(&mut self, i: usize, val: T) {
-        if self.vec.len() <= i {
-            let fill = self.fill;
-            self.vec.resize_with(i + 1, || fill);
-        }
-        self.vec[i] = val;
+        *self.get_mut(i) = val;
     }
 
-    pub fn get_copy(&self, i: usize) -> T {
-        self.vec.get(i).copied().unwrap_or(self.fill)
+    pub fn get(&self, i: usize) -> &T {
+        self.vec.get(i).unwrap_or(&self.fill)
     }
 
     pub fn get_mut(&mut self, i: usize) -> &mut T {
         if self.vec.len() <= i {
-            let fill = self.fill;
-            self.vec.resize_with(i + 1, || fill);
+            let fill = &self.fill;
+            self.vec.resize_with(i + 1, || fill.clone());
         }
         &mut self.vec[i]
     }
 
+    pub fn len(&self) -> usize {
+        self.vec.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vec.is_empty()
+    }
+
+    /// The entries up to the highest index that was accessed via
+    /// `set` or `get_mut`, with their index; entries that were never
+    /// set have the fill value.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &T)> {
+        self.vec.iter().enumerate()
+    }
+
+    /// Drop the trailing entries that are equal to the fill value
+    /// (thus reducing `len`), and release unused capacity.
+    pub fn shrink_to_fit(&mut self)
+    where
+        T: PartialEq,
+    {
+        while self.vec.last() == Some(&self.fill) {
+            self.vec.pop();
+        }
+        self.vec.shrink_to_fit();
+    }
+}
+
+impl<T: Clone + Copy> AutoVec<T> {
+    pub fn get_copy(&self, i: usize) -> T {
+        *self.get(i)
+    }
+
     pub fn saturating_inc(&mut self, i: usize) -> T
     where
         T: SaturatingAdd + From<u8>,
@@ -45,8 +74,4 @@ impl<T: Clone + Copy> AutoVec<T> {
         *val = new_val;
         new_val
     }
-
-    pub fn len(&self) -> usize {
-        self.vec.len()
-    }
 }
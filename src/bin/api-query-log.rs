@@ -1,21 +1,34 @@
 use std::{
+    collections::{BTreeMap, BTreeSet},
     fs::read_to_string,
     path::{Path, PathBuf},
     process::exit,
     str::FromStr,
     sync::{mpsc::SendError, Arc},
+    thread,
+    time::{Duration, Instant, SystemTime},
 };
 
 use anyhow::{anyhow, bail, Context, Result};
 use api_query::{
-    auto_vec::AutoVec,
     get_terminal_width::get_terminal_width,
-    log_csv::{LogCsvExtendedFormat, LogCsvReader, LogCsvRecord, LogCsvWriter},
+    log_csv::{
+        open_log_reader, BadRow, LogCsvExtendedFormat, LogCsvOk, LogCsvRecord, LogCsvResult,
+        LogCsvWriter, LogMeta, DEFAULT_CHANNEL_CAPACITY,
+    },
+    log_sums::{CompareKey, DuplicateCheck, SumError, Sums},
     my_crc::Crc,
-    types::{Queries, QueryReference, QueryReferenceWithRepetition},
+    percentiles::Percentiles,
+    summary::size_histogram,
+    time::{HumanDuration, Rfc3339TimeWrap, TimestampZone},
+    types::{
+        Queries, QueryReference, QueryReferenceWithRepetition, RecordSeparator,
+        DEFAULT_MAX_QUERY_BYTES,
+    },
 };
 use clap::Parser;
-use regex::Regex;
+use itertools::Itertools;
+use regex::{Regex, RegexSet};
 use reqwest::StatusCode;
 
 fn is_any_error(status: StatusCode) -> bool {
@@ -45,6 +58,11 @@ enum Command {
         #[clap(short, long)]
         force: bool,
 
+        /// The record separator that was used for the queries file
+        /// (see `api-query iter --help`)
+        #[clap(long)]
+        record_separator: Option<RecordSeparator>,
+
         /// Path to the matching queries file for the given CSV log
         /// files
         queries: PathBuf,
@@ -59,14 +77,16 @@ enum Command {
 
     /// Compare two api-query CSV log files
     Compare {
-        /// Ignore queries matching this regex
+        /// Ignore queries matching this regex. Can be given multiple
+        /// times, queries matching any of them are ignored.
         #[clap(long)]
-        ignore: Option<Regex>,
+        ignore: Vec<Regex>,
 
         /// Ignore queries matching the regex in the file with the
-        /// given path (with whitespace trimmed from the end)
+        /// given path (with whitespace trimmed from the end). Can be
+        /// given multiple times, and combined with `--ignore`.
         #[clap(long)]
-        ignore_from: Option<PathBuf>,
+        ignore_from: Vec<PathBuf>,
 
         /// Do not report differences in messages from HTTP error
         /// responses as errors (those are still printed and counted
@@ -79,68 +99,205 @@ enum Command {
         #[clap(long)]
         queries: Option<PathBuf>,
 
-        /// Show the ignored queries
+        /// The record separator that was used for the `--queries`
+        /// file (see `api-query iter --help`)
+        #[clap(long)]
+        record_separator: Option<RecordSeparator>,
+
+        /// Show the ignored queries, and the time taken to parse
+        /// each log file
         #[clap(short, long)]
         verbose: bool,
 
+        /// Check that no (line, repetition) pair occurs more than
+        /// once in a log file; duplicates are reported with their
+        /// position and make the comparison fail
+        #[clap(long)]
+        check: bool,
+
+        /// Skip rows of the log files that can't be parsed (reporting
+        /// them on stderr) instead of stopping with an error
+        #[clap(long)]
+        skip_bad_rows: bool,
+
+        /// Only compare the lengths and CRCs of the responses, not
+        /// their HTTP status (e.g. to accept 200 vs. 203 with the
+        /// same body)
+        #[clap(long)]
+        ignore_status: bool,
+
+        /// Only compare the CRCs of the responses, neither their
+        /// status nor their lengths (implies `--ignore-status`; e.g.
+        /// for old logs with unreliable lengths). The lengths are
+        /// still shown.
+        #[clap(long)]
+        crc_only: bool,
+
+        /// Fail if a query line has results in only one of the log
+        /// files (e.g. because one run was stopped early), instead of
+        /// listing those lines and comparing the rest
+        #[clap(long)]
+        strict: bool,
+
+        /// Ignore queries whose first successful response in either
+        /// log file has this CRC (as shown in the log files, e.g.
+        /// `crc:123`), e.g. for known nondeterministic queries. Can
+        /// be given multiple times.
+        #[clap(long)]
+        ignore_crc: Vec<Crc>,
+
+        /// Warn if the time ranges in which the requests in the two
+        /// log files were started don't overlap, or if the first
+        /// requests were started more than this many seconds apart,
+        /// e.g. when comparing runs done concurrently on two machines
+        /// (whose clocks may be skewed).
+        #[clap(long)]
+        max_start_skew: Option<u64>,
+
+        /// Also treat it as a difference if the first successful
+        /// responses for a query needed a different number of
+        /// attempts (see `api-query iter --retry-status`); only
+        /// possible where both logs have the attempts column
+        #[clap(long)]
+        compare_attempts: bool,
+
         /// The first CSV log file to compare
         a: PathBuf,
         /// The second CSV log file to compare
         b: PathBuf,
     },
+
+    /// Show the distribution of the latencies and response lengths
+    /// in an api-query CSV log file (min, p50, p99, max, and a
+    /// histogram of the lengths)
+    Stats {
+        /// Show the distributions for each response status
+        /// separately, too
+        #[clap(long)]
+        by_status: bool,
+
+        /// Also count the distinct CRCs of the responses (of any
+        /// status), compared to the number of distinct queries
+        /// answered, with a warning if there are suspiciously few
+        /// (e.g. the endpoint answers everything with the same error
+        /// page, which `compare` would accept if it does so in both
+        /// runs)
+        #[clap(long)]
+        crc_cardinality: bool,
+
+        /// Skip rows of the log file that can't be parsed (reporting
+        /// them on stderr) instead of stopping with an error
+        #[clap(long)]
+        skip_bad_rows: bool,
+
+        /// Path to the CSV log file
+        path: PathBuf,
+    },
 }
 
-struct Sums {
-    path: Arc<Path>,
-    sums: AutoVec<(StatusCode, usize, Crc)>,
-    seen: AutoVec<u8>,
-    errors: Vec<SumError>,
-    successes: usize,
+/// For `stats --crc-cardinality`: warn if there are fewer distinct
+/// CRCs than this fraction of the distinct queries answered.
+const SUSPICIOUS_CRC_RATIO: f64 = 0.1;
+
+/// For `stats --crc-cardinality`: the number of responses per CRC,
+/// and the queries they answer
+struct CrcCardinality {
+    crc_counts: BTreeMap<Crc, u64>,
+    successful_queries: BTreeSet<QueryReference>,
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
-enum SumError {
-    NonMatchingCrc {
-        reference: QueryReferenceWithRepetition,
-        crc: (StatusCode, usize, Crc),
-    },
+/// The result of `CrcCardinality::summary`
+#[derive(Debug, PartialEq)]
+struct CrcCardinalitySummary {
+    num_responses: u64,
+    num_distinct_crcs: usize,
+    num_distinct_queries: usize,
+    /// The most common CRC and the number of its responses
+    most_common: Option<(Crc, u64)>,
+    /// If there are suspiciously few distinct CRCs (see
+    /// `SUSPICIOUS_CRC_RATIO`)
+    warning: Option<String>,
 }
 
-impl Sums {
-    fn new(path: Arc<Path>) -> Self {
-        Self {
-            path,
-            sums: AutoVec::new((StatusCode::from_u16(200).unwrap(), 13131313131313, Crc(0))),
-            seen: AutoVec::new(0),
-            errors: Default::default(),
-            successes: Default::default(),
+impl CrcCardinality {
+    fn new() -> Self {
+        CrcCardinality {
+            crc_counts: BTreeMap::new(),
+            successful_queries: BTreeSet::new(),
         }
     }
 
-    fn len(&self) -> usize {
-        assert_eq!(self.sums.len(), self.seen.len());
-        self.sums.len()
+    fn add(&mut self, crc: Crc, query_reference: QueryReference) {
+        *self.crc_counts.entry(crc).or_insert(0) += 1;
+        self.successful_queries.insert(query_reference);
     }
 
-    fn add(&mut self, record: &LogCsvRecord) {
-        if let Some(crc) = record.status_length_crc() {
-            let i = record.query_reference().query_index_usize();
-            let now_uses = self.seen.saturating_inc(i);
-            if now_uses > 1 {
-                let first_crc = self.sums.get_copy(i);
-                if crc == first_crc {
-                    self.successes += 1;
-                } else {
-                    self.errors.push(SumError::NonMatchingCrc {
-                        reference: record.query_reference_with_repetition(),
-                        crc,
-                    });
-                }
-            } else {
-                self.sums.set(i, crc);
-            }
-        } else {
-            // ignore errors
+    fn summary(&self) -> CrcCardinalitySummary {
+        let num_distinct_crcs = self.crc_counts.len();
+        let num_distinct_queries = self.successful_queries.len();
+        let warning = (num_distinct_queries > 1
+            && (num_distinct_crcs as f64) < num_distinct_queries as f64 * SUSPICIOUS_CRC_RATIO)
+            .then(|| {
+                format!(
+                    "suspiciously few distinct CRCs ({num_distinct_crcs} for \
+                     {num_distinct_queries} queries), is the endpoint giving the same \
+                     response for everything?"
+                )
+            });
+        CrcCardinalitySummary {
+            num_responses: self.crc_counts.values().sum(),
+            num_distinct_crcs,
+            num_distinct_queries,
+            most_common: self
+                .crc_counts
+                .iter()
+                .max_by_key(|(_, &count)| count)
+                .map(|(&crc, &count)| (crc, count)),
+            warning,
+        }
+    }
+}
+
+/// Latencies (in seconds) and lengths of responses, for `stats`
+struct ResponseStats {
+    latencies: Percentiles,
+    lengths: Percentiles,
+}
+
+impl ResponseStats {
+    fn new() -> Self {
+        ResponseStats {
+            latencies: Percentiles::exact(),
+            lengths: Percentiles::exact(),
+        }
+    }
+
+    fn add(&mut self, latency: f64, length: usize) {
+        self.latencies.add(latency);
+        self.lengths.add(length as f64);
+    }
+
+    fn print(&mut self, title: &str) {
+        let latencies = &mut self.latencies;
+        let lengths = &mut self.lengths;
+        let secs = |secs: f64| HumanDuration(Duration::from_secs_f64(secs.max(0.)));
+        println!("{title} ({} responses):", lengths.len());
+        println!(
+            "  latency: min {}, p50 {}, p99 {}, max {}",
+            secs(latencies.min()),
+            secs(latencies.quantile(0.5)),
+            secs(latencies.quantile(0.99)),
+            secs(latencies.max())
+        );
+        println!(
+            "  length: min {}, p50 {}, p99 {}, max {} bytes",
+            lengths.min(),
+            lengths.quantile(0.5),
+            lengths.quantile(0.99),
+            lengths.max()
+        );
+        for line in size_histogram(lengths, get_terminal_width().saturating_sub(2)).lines() {
+            println!("  {line}");
         }
     }
 }
@@ -148,7 +305,7 @@ impl Sums {
 struct QueriesWithIgnore {
     path: Arc<Path>,
     queries: Arc<Queries>,
-    ignore_regex: Regex,
+    ignore_regexes: RegexSet,
 }
 
 impl QueriesWithIgnore {
@@ -163,15 +320,57 @@ impl QueriesWithIgnore {
                     self.path
                 )
             })?;
-        Ok(self.ignore_regex.is_match(query.string))
+        Ok(self.ignore_regexes.is_match(query.string))
     }
 }
 
-fn sums_from_file(ignore: Option<&QueriesWithIgnore>, path: Arc<Path>) -> Result<(usize, Sums)> {
-    let mut sums = Sums::new(path.clone());
+/// Options for reading log files in `compare`
+#[derive(Debug, Clone, Copy)]
+struct ReadOptions {
+    /// Check for duplicate (line, repetition) entries
+    check: bool,
+    skip_bad_rows: bool,
+    compare_key: CompareKey,
+}
+
+fn sums_from_file(
+    ignore: Option<&QueriesWithIgnore>,
+    options: ReadOptions,
+    path: Arc<Path>,
+) -> Result<(usize, Sums)> {
+    let ReadOptions {
+        check,
+        skip_bad_rows,
+        compare_key,
+    } = options;
+    let mut sums = Sums::new(path.clone(), compare_key);
     let mut num_ignored = 0;
-    for record in LogCsvReader::open(path)? {
-        let record = record?;
+    let mut duplicate_check = check.then(DuplicateCheck::new);
+    let mut num_duplicates = 0;
+    let mut num_bad_rows = 0;
+    for (i, record) in open_log_reader(path.clone())?.enumerate() {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) if skip_bad_rows && e.is::<BadRow>() => {
+                eprintln!("api-query-log: skipping {e:#}");
+                num_bad_rows += 1;
+                continue;
+            }
+            Err(e) => Err(e)?,
+        };
+        if let Some(duplicate_check) = &mut duplicate_check {
+            let reference = record.query_reference_with_repetition();
+            if duplicate_check.check(reference) {
+                num_duplicates += 1;
+                eprintln!(
+                    "api-query-log: {path:?}: duplicate entry for line {}, repetition {} \
+                     in record {} (1-based, excluding the header)",
+                    reference.query_reference,
+                    reference.repetition,
+                    i + 1
+                );
+            }
+        }
         if let Some(ignore) = ignore {
             if ignore.ignore(record.query_reference())? {
                 num_ignored += 1;
@@ -180,15 +379,259 @@ fn sums_from_file(ignore: Option<&QueriesWithIgnore>, path: Arc<Path>) -> Result
         }
         sums.add(&record);
     }
+    if num_bad_rows > 0 {
+        eprintln!("api-query-log: skipped {num_bad_rows} bad rows in {path:?}");
+    }
+    if num_duplicates > 0 {
+        bail!("{num_duplicates} duplicate (line, repetition) entries in {path:?}")
+    }
     Ok((num_ignored, sums))
 }
 
+/// For `compare --max-start-skew`: the warning to show if the runs
+/// recorded in the two logs (with the given start time ranges) were
+/// apparently not concurrent, or not started together (or the clocks
+/// of the machines they ran on differ).
+fn check_start_ranges(
+    (a_first, a_last): (SystemTime, SystemTime),
+    (b_first, b_last): (SystemTime, SystemTime),
+    max_start_skew: Duration,
+) -> Option<String> {
+    let time = |t| Rfc3339TimeWrap(t, TimestampZone::Utc);
+    if a_last < b_first || b_last < a_first {
+        return Some(format!(
+            "the runs don't overlap in time (requests started \
+             {} to {} vs. {} to {}), thus were not concurrent, or the clocks differ",
+            time(a_first),
+            time(a_last),
+            time(b_first),
+            time(b_last)
+        ));
+    }
+    let skew = a_first
+        .duration_since(b_first)
+        .or_else(|_| b_first.duration_since(a_first))
+        .unwrap_or_default();
+    if skew > max_start_skew {
+        Some(format!(
+            "the first requests of the runs were started {} apart \
+             ({} vs. {}), thus the runs were not started together, or the clocks are skewed",
+            HumanDuration(skew),
+            time(a_first),
+            time(b_first)
+        ))
+    } else {
+        None
+    }
+}
+
+/// Options for `compare_sums`
+struct CompareOptions<'t> {
+    accept_error_differences: bool,
+    strict: bool,
+    ignore_crc: &'t [Crc],
+    compare_attempts: bool,
+    /// The queries file, for showing the query strings of differing
+    /// queries
+    queries: Option<&'t Queries>,
+}
+
+/// The outcome of comparing the first successful results of each
+/// query in two log files, see `compare_sums`
+#[derive(Debug, Default)]
+struct Comparison {
+    /// The number of hard errors, i.e. a count > 0 is reported with
+    /// an error exit status (only includes differences in HTTP error
+    /// responses if --accept-error-differences was not given)
+    num_errors: usize,
+    /// The number of differences in HTTP error responses (regardless
+    /// of whether --accept-error-differences was given)
+    num_error_errors: usize,
+    num_ignored_error_differences: usize,
+    num_same: usize,
+    num_ignored_counted: usize,
+    /// The number of queries whose request bodies differ (only known
+    /// if both logs were written with --crc-request)
+    num_request_differences: usize,
+    /// The number of queries skipped due to --ignore-crc
+    num_ignored_crc: usize,
+    /// Query lines with results in only one of the logs
+    only_a: Vec<QueryReference>,
+    only_b: Vec<QueryReference>,
+}
+
+/// Compare the first successful result of each query in `a` with
+/// the one in `b`, printing a table of the differing ones.
+fn compare_sums(a: &Sums, b: &Sums, options: &CompareOptions) -> Result<Comparison> {
+    let CompareOptions {
+        accept_error_differences,
+        strict,
+        ignore_crc,
+        compare_attempts,
+        queries,
+    } = *options;
+    if strict && a.len() != b.len() {
+        bail!(
+            "the logs use differing numbers of query entries: {} vs. {}",
+            a.len(),
+            b.len()
+        );
+    }
+    let mut comparison = Comparison::default();
+    println!(
+        "query file line\t\
+         status 1\tlength 1\tCRC 1\t\
+         status 2\tlength 2\tCRC 2\tquery string"
+    );
+    for i in 0..a.len().max(b.len()) {
+        let reference = QueryReference {
+            query_index: i as u32,
+        };
+        match (a.first(reference), b.first(reference)) {
+            (None, None) => {
+                comparison.num_ignored_counted += 1;
+            }
+            (Some((_, _, acrc)), Some((_, _, bcrc)))
+                if ignore_crc.contains(&acrc) || ignore_crc.contains(&bcrc) =>
+            {
+                comparison.num_ignored_crc += 1;
+            }
+            (Some(alen_and_sum), Some(blen_and_sum)) => {
+                if let (Some(a_request_crc), Some(b_request_crc)) = (
+                    a.first_request_crc(reference),
+                    b.first_request_crc(reference),
+                ) {
+                    if a_request_crc != b_request_crc {
+                        comparison.num_request_differences += 1;
+                        eprintln!(
+                            "api-query-log: WARNING: the request for query line \
+                             {reference} differs: {a_request_crc} vs. {b_request_crc}"
+                        );
+                    }
+                }
+                let attempts_differ = compare_attempts
+                    && match (a.first_attempts(reference), b.first_attempts(reference)) {
+                        (Some(a_attempts), Some(b_attempts)) => {
+                            if a_attempts != b_attempts {
+                                eprintln!(
+                                    "api-query-log: query line {reference} needed \
+                                     {a_attempts} vs. {b_attempts} attempts"
+                                );
+                            }
+                            a_attempts != b_attempts
+                        }
+                        _ => false,
+                    };
+                if a.same(alen_and_sum, blen_and_sum) && !attempts_differ {
+                    comparison.num_same += 1;
+                } else {
+                    let line = i + 1;
+                    let query_string = if let Some(queries) = queries {
+                        if let Some(query) = queries.borrow_queries().get(i) {
+                            query.string
+                        } else {
+                            "<error: line is not in given query file>"
+                        }
+                    } else {
+                        "<error: missing --queries option>"
+                    };
+                    let (astatus, alen, asum) = alen_and_sum;
+                    let (bstatus, blen, bsum) = blen_and_sum;
+                    println!(
+                        "{line}\t{astatus}\t{alen}\t{asum}\t{bstatus}\t{blen}\t{bsum}\t\
+                         {query_string}"
+                    );
+                    let is_error_difference = astatus == bstatus && is_any_error(bstatus);
+                    if is_error_difference {
+                        comparison.num_error_errors += 1;
+                    }
+                    if is_error_difference && accept_error_differences {
+                        // do not count as 'hard' error
+                        comparison.num_ignored_error_differences += 1;
+                    } else {
+                        comparison.num_errors += 1;
+                    }
+                }
+            }
+            (a_first, b_first) => {
+                if strict {
+                    bail!(
+                        "query line {reference} was seen: in a: {}, in b: {}",
+                        a_first.is_some(),
+                        b_first.is_some()
+                    )
+                }
+                if a_first.is_some() {
+                    comparison.only_a.push(reference);
+                } else {
+                    comparison.only_b.push(reference);
+                }
+            }
+        }
+    }
+    Ok(comparison)
+}
+
+/// Print the repetitions of queries in `sums` that differ from the
+/// first run (except for CRCs in `ignore_crc`), returning their
+/// number.
+fn report_repetition_errors(sums: &mut Sums, ignore_crc: &[Crc]) -> usize {
+    let is_ignored = |sums: &Sums, sum_error: &SumError| match sum_error {
+        SumError::NonMatchingCrc {
+            reference,
+            crc: (_, _, crc),
+        } => {
+            ignore_crc.contains(crc)
+                || sums
+                    .first(reference.query_reference)
+                    .is_some_and(|(_, _, first_crc)| ignore_crc.contains(&first_crc))
+        }
+    };
+    let num_sum_errors = sums
+        .errors()
+        .iter()
+        .filter(|sum_error| !is_ignored(sums, sum_error))
+        .count();
+    if num_sum_errors > 0 {
+        println!("Errors in {:?}:", sums.path());
+        sums.sort_errors();
+        println!(
+            "query file line\trepetition\tfirst status\nfirst len\tfirst CRC\t\
+             subsequent status\tsubsequent len\tsubsequent CRC"
+        );
+        for sum_error in sums.errors() {
+            if is_ignored(sums, sum_error) {
+                continue;
+            }
+            match sum_error {
+                SumError::NonMatchingCrc {
+                    reference:
+                        QueryReferenceWithRepetition {
+                            query_reference,
+                            repetition,
+                        },
+                    crc: (status, len, crc),
+                } => {
+                    let (first_status, first_len, first_crc) = sums
+                        .first(*query_reference)
+                        .expect("errors only exist for seen queries");
+                    println!(
+                        "{query_reference}\t{repetition}\t{first_status}\t{first_len}\t\
+                         {first_crc}\t{status}\t{len}\t{crc}"
+                    );
+                }
+            }
+        }
+    }
+    num_sum_errors
+}
+
 fn main() -> Result<()> {
     let Opts { command } = Opts::parse();
 
     match command {
         Command::Debug { path } => {
-            for record in LogCsvReader::open(path.into())? {
+            for record in open_log_reader(path.into())? {
                 let record = record?;
                 dbg!(record);
             }
@@ -196,24 +639,30 @@ fn main() -> Result<()> {
 
         Command::Expand {
             force,
+            record_separator,
             queries,
             input,
             output,
         } => {
             let input = input.into();
             let output = output.into();
-            let queries = Queries::from_path(&queries)?.into();
-            let log = LogCsvReader::open(input)?;
+            let queries = Queries::from_path(
+                &queries,
+                DEFAULT_MAX_QUERY_BYTES,
+                &record_separator.unwrap_or_default(),
+            )?
+            .into();
+            let log = open_log_reader(input)?;
             let format = LogCsvExtendedFormat { queries };
-            let out = LogCsvWriter::create(output, force, format)?;
+            let out = LogCsvWriter::create(output, force, format, None, DEFAULT_CHANNEL_CAPACITY)?;
             enum E {
                 Anyhow(anyhow::Error),
-                Sendfail(SendError<LogCsvRecord>),
+                Sendfail(Box<SendError<LogCsvRecord>>),
             }
             match (|| -> Result<(), E> {
                 for msg in log {
                     let msg = msg.map_err(E::Anyhow)?;
-                    out.send(msg).map_err(E::Sendfail)?;
+                    out.send(msg).map_err(|e| E::Sendfail(Box::new(e)))?;
                 }
                 Ok(())
             })() {
@@ -231,49 +680,71 @@ fn main() -> Result<()> {
             ignore_from,
             accept_error_differences,
             queries,
+            record_separator,
             verbose,
+            check,
+            skip_bad_rows,
+            ignore_status,
+            crc_only,
+            strict,
+            ignore_crc,
+            max_start_skew,
+            compare_attempts,
         } => {
-            let ignore_regex =
-                if let Some(ignore) = ignore {
-                    if ignore_from.is_some() {
-                        bail!("please only give one of --ignore or --ignore-path")
-                    }
-                    Some(ignore)
-                } else if let Some(ignore_from) = ignore_from {
-                    let string = read_to_string(&ignore_from)
-                        .with_context(|| anyhow!("reading ignore file at {ignore_from:?}"))?;
-                    let re = string.trim_end();
-                    if re.is_empty() {
-                        if verbose {
-                            eprintln!(
-                                "ignoring --ignore-from file {ignore_from:?} since it is empty; \
-                                 if you want to match a space, please append `{{1}}`"
-                            );
-                        }
-                        None
-                    } else {
-                        Some(Regex::from_str(re).with_context(|| {
-                            anyhow!("parsing regex from file at {ignore_from:?}")
-                        })?)
+            let read_options = ReadOptions {
+                check,
+                skip_bad_rows,
+                compare_key: if crc_only {
+                    CompareKey::Crc
+                } else if ignore_status {
+                    CompareKey::LengthCrc
+                } else {
+                    CompareKey::StatusLengthCrc
+                },
+            };
+            let mut ignore_patterns: Vec<String> =
+                ignore.iter().map(|re| re.as_str().to_owned()).collect();
+            for ignore_from in &ignore_from {
+                let string = read_to_string(ignore_from)
+                    .with_context(|| anyhow!("reading ignore file at {ignore_from:?}"))?;
+                let re = string.trim_end();
+                if re.is_empty() {
+                    if verbose {
+                        eprintln!(
+                            "ignoring --ignore-from file {ignore_from:?} since it is empty; \
+                             if you want to match a space, please append `{{1}}`"
+                        );
                     }
                 } else {
-                    None
-                };
+                    Regex::from_str(re)
+                        .with_context(|| anyhow!("parsing regex from file at {ignore_from:?}"))?;
+                    ignore_patterns.push(re.into());
+                }
+            }
+            let ignore_regexes = if ignore_patterns.is_empty() {
+                None
+            } else {
+                Some(RegexSet::new(&ignore_patterns).context("combining the ignore regexes")?)
+            };
 
             let path_and_queries: Option<(Arc<Path>, Arc<Queries>)> = if let Some(queries) = queries
             {
                 let path: Arc<Path> = queries.into();
-                let queries = Queries::from_path(&*path)?;
+                let queries = Queries::from_path(
+                    &path,
+                    DEFAULT_MAX_QUERY_BYTES,
+                    &record_separator.unwrap_or_default(),
+                )?;
                 Some((path, queries.into()))
             } else {
                 None
             };
 
-            let queries_with_ignore = if let Some(ignore_regex) = ignore_regex {
+            let queries_with_ignore = if let Some(ignore_regexes) = ignore_regexes {
                 if let Some((path, queries)) = &path_and_queries {
                     let queries_with_ignore = QueriesWithIgnore {
                         path: path.clone(),
-                        ignore_regex,
+                        ignore_regexes,
                         queries: queries.clone(),
                     };
                     if verbose {
@@ -301,93 +772,95 @@ fn main() -> Result<()> {
             } else {
                 None
             };
-            let (num_a_original_ignored, a) =
-                sums_from_file(queries_with_ignore.as_ref(), a.into())?;
-            let (num_b_original_ignored, b) =
-                sums_from_file(queries_with_ignore.as_ref(), b.into())?;
-            if a.len() != b.len() {
+            let a_meta = LogMeta::read(&a)?;
+            let b_meta = LogMeta::read(&b)?;
+            if a_meta.hash_prefix_bytes != b_meta.hash_prefix_bytes {
+                let setting = |meta: &LogMeta| {
+                    meta.hash_prefix_bytes
+                        .map_or_else(|| "(none)".into(), |n| n.to_string())
+                };
                 bail!(
-                    "the logs use differing numbers of query entries: {} vs. {}",
-                    a.len(),
-                    b.len()
-                );
+                    "the logs were written with different --hash-prefix-bytes settings \
+                     ({} vs. {}), thus their CRCs are not comparable",
+                    setting(&a_meta),
+                    setting(&b_meta)
+                )
             }
-            // The number of hard errors, i.e. a count > 0 is reported
-            // with an error exit status (only includes differences
-            // in HTTP error responses if --accept-error-differences
-            // was not given)
-            let mut num_errors: usize = 0;
-            // The number of differences in HTTP error responses
-            // (regardless of whether --accept-error-differences was
-            // given)
-            let mut num_error_errors: usize = 0;
-            let mut num_ignored_error_differences: usize = 0;
-            let mut num_same: usize = 0;
-            let mut num_ignored_counted: usize = 0;
-            println!(
-                "query file line\t\
-                 status 1\tlength 1\tCRC 1\t\
-                 status 2\tlength 2\tCRC 2\tquery string"
-            );
-            for i in 0..a.len() {
-                match (a.seen.get_copy(i) > 0, b.seen.get_copy(i) > 0) {
-                    (false, false) => {
-                        num_ignored_counted += 1;
-                    }
-                    (true, true) => {
-                        let alen_and_sum = a.sums.get_copy(i);
-                        let blen_and_sum = b.sums.get_copy(i);
-                        if alen_and_sum == blen_and_sum {
-                            num_same += 1;
-                        } else {
-                            let line = i + 1;
-                            let query_string = if let Some((_, query)) = &path_and_queries {
-                                if let Some(query) = query.borrow_queries().get(i) {
-                                    &query.string
-                                } else {
-                                    "<error: line is not in given query file>"
-                                }
-                            } else {
-                                "<error: missing --queries option>"
-                            };
-                            let (astatus, alen, asum) = alen_and_sum;
-                            let (bstatus, blen, bsum) = blen_and_sum;
-                            println!(
-                                "{line}\t{astatus}\t{alen}\t{asum}\t{bstatus}\t{blen}\t{bsum}\t\
-                                 {query_string}"
+            if a_meta.ndjson != b_meta.ndjson {
+                bail!(
+                    "only one of the logs was written with --ndjson, \
+                     thus their CRCs are not comparable"
+                )
+            }
+            // Parse both logs in parallel
+            let (a_result, b_result) = thread::scope(|scope| {
+                let parse = |path: PathBuf| {
+                    let ignore = queries_with_ignore.as_ref();
+                    scope.spawn(move || {
+                        let start = Instant::now();
+                        let result = sums_from_file(ignore, read_options, path.as_path().into());
+                        if verbose {
+                            eprintln!(
+                                "api-query-log: parsed {path:?} in {}",
+                                HumanDuration(start.elapsed())
                             );
-                            let is_error_difference = astatus == bstatus && is_any_error(bstatus);
-                            if is_error_difference {
-                                num_error_errors += 1;
-                            }
-                            if is_error_difference && accept_error_differences {
-                                // do not count as 'hard' error
-                                num_ignored_error_differences += 1;
-                            } else {
-                                num_errors += 1;
-                            }
                         }
-                    }
-                    (aseen, bseen) => {
-                        bail!(
-                            "bug?: query line {} was seeen: in a: {aseen}, in b: {bseen}",
-                            i + 1
-                        )
-                    }
+                        result
+                    })
+                };
+                let a = parse(a);
+                let b = parse(b);
+                (a.join(), b.join())
+            });
+            let join = |result: thread::Result<_>| -> Result<(usize, Sums)> {
+                result.map_err(|e| anyhow!("log parser thread panicked: {e:?}"))?
+            };
+            let (num_a_original_ignored, a) = join(a_result)?;
+            let (num_b_original_ignored, b) = join(b_result)?;
+            if let (Some(max_start_skew), Some(a_range), Some(b_range)) =
+                (max_start_skew, a.start_range(), b.start_range())
+            {
+                if let Some(warning) =
+                    check_start_ranges(a_range, b_range, Duration::from_secs(max_start_skew))
+                {
+                    eprintln!("api-query-log: WARNING: {warning}");
                 }
             }
+            let Comparison {
+                mut num_errors,
+                num_error_errors,
+                num_ignored_error_differences,
+                num_same,
+                num_ignored_counted,
+                num_request_differences,
+                num_ignored_crc,
+                only_a,
+                only_b,
+            } = compare_sums(
+                &a,
+                &b,
+                &CompareOptions {
+                    accept_error_differences,
+                    strict,
+                    ignore_crc: &ignore_crc,
+                    compare_attempts,
+                    queries: path_and_queries.as_ref().map(|(_, queries)| &**queries),
+                },
+            )?;
             let num_total_queries = if let Some((_path, queries)) = &path_and_queries {
                 queries.borrow_queries().len()
             } else {
                 // If there's no queries, then we can't get the count
                 // from it, but we also don't filter, ergo can rely on
                 // the count being correct (no gaps)
-                assert_eq!(a.seen.len(), b.seen.len());
-                a.seen.len()
+                a.len().max(b.len())
             };
-            assert!(num_errors + num_same + num_ignored_counted <= num_total_queries);
+            let num_one_sided = only_a.len() + only_b.len() + num_ignored_crc;
+            assert!(
+                num_errors + num_same + num_ignored_counted + num_one_sided <= num_total_queries
+            );
             let num_ignored_calculated = num_total_queries
-                - (num_errors + num_same + num_ignored_counted)
+                - (num_errors + num_same + num_ignored_counted + num_one_sided)
                 - num_ignored_error_differences;
             println!(
                 "=> {num_errors} queries gave CRC differences to be treated as errors, \
@@ -398,42 +871,401 @@ fn main() -> Result<()> {
             );
             // ^ XX what are the `num_*_original_ignored` again? They can
             //      be non-zero while `num_ignored_calculated` is 0.
-
-            for mut sums in [a, b] {
-                if !sums.errors.is_empty() {
-                    num_errors += sums.errors.len();
-                    println!("Errors in {:?}:", sums.path);
-                    sums.errors.sort();
+            if !ignore_crc.is_empty() {
+                println!("=> {num_ignored_crc} queries were ignored via --ignore-crc");
+            }
+            if a_meta.run_label.is_some() || b_meta.run_label.is_some() {
+                let label = |meta: &LogMeta| {
+                    meta.run_label
+                        .as_ref()
+                        .map_or_else(|| "(none)".into(), |label| format!("{label:?}"))
+                };
+                println!("=> run labels: {} vs. {}", label(&a_meta), label(&b_meta));
+            }
+            if num_request_differences > 0 {
+                println!(
+                    "=> {num_request_differences} queries were sent with differing request bodies"
+                );
+            }
+            for (only, sums) in [(&only_a, &a), (&only_b, &b)] {
+                if !only.is_empty() {
                     println!(
-                        "query file line\trepetition\tfirst status\nfirst len\tfirst CRC\t\
-                         subsequent status\tsubsequent len\tsubsequent CRC"
+                        "=> {} queries only have results in {:?} (not compared), lines: {}",
+                        only.len(),
+                        sums.path(),
+                        only.iter().join(", ")
                     );
-                    for sum_error in &sums.errors {
-                        match sum_error {
-                            SumError::NonMatchingCrc {
-                                reference:
-                                    QueryReferenceWithRepetition {
-                                        query_reference,
-                                        repetition,
-                                    },
-                                crc: (status, len, crc),
-                            } => {
-                                let (first_status, first_len, first_crc) =
-                                    sums.sums.get_copy(query_reference.query_index_usize());
-                                println!(
-                                    "{query_reference}\t{repetition}\t{first_status}\t{first_len}\t\
-                                     {first_crc}\t{status}\t{len}\t{crc}");
-                            }
-                        }
-                    }
                 }
             }
 
+            for mut sums in [a, b] {
+                num_errors += report_repetition_errors(&mut sums, &ignore_crc);
+            }
+
             if num_errors > 0 {
                 exit(1);
             }
         }
+
+        Command::Stats {
+            by_status,
+            crc_cardinality,
+            skip_bad_rows,
+            path,
+        } => {
+            let mut all = ResponseStats::new();
+            let mut crc_cardinality = crc_cardinality.then(CrcCardinality::new);
+            let mut per_status = BTreeMap::<StatusCode, ResponseStats>::new();
+            let mut num_errors = 0;
+            let mut num_bad_rows = 0;
+            for record in open_log_reader(path.as_path().into())? {
+                let record = match record {
+                    Ok(record) => record,
+                    Err(e) if skip_bad_rows && e.is::<BadRow>() => {
+                        eprintln!("api-query-log: skipping {e:#}");
+                        num_bad_rows += 1;
+                        continue;
+                    }
+                    Err(e) => Err(e)?,
+                };
+                match record.result() {
+                    LogCsvResult::Ok(LogCsvOk {
+                        status,
+                        length,
+                        crc,
+                        ..
+                    }) => {
+                        all.add(record.4, *length);
+                        if let Some(crc_cardinality) = &mut crc_cardinality {
+                            crc_cardinality.add(*crc, record.query_reference());
+                        }
+                        if by_status {
+                            per_status
+                                .entry(*status)
+                                .or_insert_with(ResponseStats::new)
+                                .add(record.4, *length);
+                        }
+                    }
+                    LogCsvResult::Err(..) => num_errors += 1,
+                }
+            }
+            if num_bad_rows > 0 {
+                eprintln!("api-query-log: skipped {num_bad_rows} bad rows in {path:?}");
+            }
+            all.print("all");
+            for (status, stats) in &mut per_status {
+                stats.print(&format!("status {status}"));
+            }
+            println!("=> {num_errors} errors without a response are not included");
+            if let Some(crc_cardinality) = &crc_cardinality {
+                let CrcCardinalitySummary {
+                    num_responses,
+                    num_distinct_crcs,
+                    num_distinct_queries,
+                    most_common,
+                    warning,
+                } = crc_cardinality.summary();
+                println!(
+                    "=> {num_distinct_crcs} distinct CRCs in {num_responses} responses to \
+                     {num_distinct_queries} distinct queries"
+                );
+                if let Some((crc, count)) = most_common {
+                    println!("=> the most common CRC is {crc}, in {count} responses");
+                }
+                if let Some(warning) = warning {
+                    eprintln!("api-query-log: WARNING: {warning}");
+                }
+            }
+        }
     }
 
     Ok(())
 }
+
+/// A file in the temp dir for the tests, with a name unique to the
+/// test `name`; removed when dropped, also when an assertion fails.
+#[cfg(test)]
+struct TestFile {
+    path: Arc<Path>,
+}
+
+#[cfg(test)]
+impl TestFile {
+    /// Just the path, with the given extension (e.g. `csv.gz`); the
+    /// file is created by the test.
+    fn new(name: &str, extension: &str) -> Self {
+        let path = std::env::temp_dir()
+            .join(format!(
+                "api-query-log-{name}-{}.{extension}",
+                std::process::id()
+            ))
+            .into();
+        Self { path }
+    }
+
+    fn with_contents(name: &str, extension: &str, contents: &str) -> Result<Self> {
+        let file = Self::new(name, extension);
+        std::fs::write(&file.path, contents)?;
+        Ok(file)
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Write a log file for the tests, with the given `(line,
+/// repetition, status, crc)` rows (all with length 5).
+#[cfg(test)]
+fn write_test_log(name: &str, rows: &[(u32, u32, u16, u64)]) -> Result<TestFile> {
+    let mut log =
+        "line in query file,repetition,start,end,d,Ok/Err,status,length,crc,error\n".to_string();
+    for (line, repetition, status, crc) in rows {
+        let status = StatusCode::from_u16(*status)?;
+        log.push_str(&format!(
+            "{line},{repetition},1.5,2.5,1,Ok,{status},5,crc:{crc},\n"
+        ));
+    }
+    TestFile::with_contents(name, "csv", &log)
+}
+
+#[cfg(test)]
+const TEST_READ_OPTIONS: ReadOptions = ReadOptions {
+    check: false,
+    skip_bad_rows: false,
+    compare_key: CompareKey::StatusLengthCrc,
+};
+
+#[cfg(test)]
+const TEST_COMPARE_OPTIONS: CompareOptions = CompareOptions {
+    accept_error_differences: false,
+    strict: false,
+    ignore_crc: &[],
+    compare_attempts: false,
+    queries: None,
+};
+
+#[test]
+fn t_compare_only_a_only_b_strict() -> Result<()> {
+    let a_file = write_test_log(
+        "t_compare_strict-a",
+        &[
+            (1, 0, 200, 1),
+            (2, 0, 200, 2),
+            (3, 0, 200, 3),
+            (5, 0, 200, 5),
+        ],
+    )?;
+    let b_file = write_test_log(
+        "t_compare_strict-b",
+        &[
+            (1, 0, 200, 1),
+            (2, 0, 200, 9),
+            (4, 0, 200, 4),
+            (5, 0, 200, 5),
+        ],
+    )?;
+    let short_file = write_test_log("t_compare_strict-short", &[(1, 0, 200, 1), (2, 0, 200, 2)])?;
+    let (_, a) = sums_from_file(None, TEST_READ_OPTIONS, a_file.path.clone())?;
+    let (_, b) = sums_from_file(None, TEST_READ_OPTIONS, b_file.path.clone())?;
+    let (_, short) = sums_from_file(None, TEST_READ_OPTIONS, short_file.path.clone())?;
+    let reference = |query_index| QueryReference { query_index };
+
+    let comparison = compare_sums(&a, &b, &TEST_COMPARE_OPTIONS)?;
+    assert_eq!(comparison.num_same, 2);
+    assert_eq!(comparison.num_errors, 1);
+    assert_eq!(comparison.only_a, [reference(2)]);
+    assert_eq!(comparison.only_b, [reference(3)]);
+
+    let comparison = compare_sums(&a, &short, &TEST_COMPARE_OPTIONS)?;
+    assert_eq!(comparison.num_same, 2);
+    assert_eq!(comparison.num_errors, 0);
+    assert_eq!(comparison.only_a, [reference(2), reference(4)]);
+    assert!(comparison.only_b.is_empty());
+
+    let strict = CompareOptions {
+        strict: true,
+        ..TEST_COMPARE_OPTIONS
+    };
+    let error = |a, b| compare_sums(a, b, &strict).unwrap_err().to_string();
+    assert_eq!(
+        error(&a, &b),
+        "query line 3 was seen: in a: true, in b: false"
+    );
+    assert_eq!(
+        error(&a, &short),
+        "the logs use differing numbers of query entries: 5 vs. 2"
+    );
+    Ok(())
+}
+
+#[test]
+fn t_compare_ignore_regexes() -> Result<()> {
+    let queries_file =
+        TestFile::with_contents("t_compare_ignore_regexes", "txt", "alpha\nbeta\ngamma\n")?;
+    let ignore = QueriesWithIgnore {
+        path: queries_file.path.clone(),
+        queries: Queries::from_path(
+            &queries_file.path,
+            DEFAULT_MAX_QUERY_BYTES,
+            &Default::default(),
+        )?
+        .into(),
+        ignore_regexes: RegexSet::new(["^alpha$", "^gam"])?,
+    };
+    let a_file = write_test_log(
+        "t_compare_ignore_regexes-a",
+        &[(1, 0, 200, 1), (2, 0, 200, 2), (3, 0, 200, 3)],
+    )?;
+    let b_file = write_test_log(
+        "t_compare_ignore_regexes-b",
+        &[(1, 0, 200, 7), (2, 0, 200, 2), (3, 0, 200, 8)],
+    )?;
+    let (a_ignored, a) = sums_from_file(Some(&ignore), TEST_READ_OPTIONS, a_file.path.clone())?;
+    let (b_ignored, b) = sums_from_file(Some(&ignore), TEST_READ_OPTIONS, b_file.path.clone())?;
+    assert_eq!((a_ignored, b_ignored), (2, 2));
+
+    let comparison = compare_sums(&a, &b, &TEST_COMPARE_OPTIONS)?;
+    assert_eq!(comparison.num_same, 1);
+    assert_eq!(comparison.num_errors, 0);
+    assert_eq!(comparison.num_ignored_counted, 1);
+    Ok(())
+}
+
+#[test]
+fn t_compare_ignore_crc() -> Result<()> {
+    let a_file = write_test_log(
+        "t_compare_ignore_crc-a",
+        &[(1, 0, 200, 1), (2, 0, 200, 5), (2, 1, 200, 6)],
+    )?;
+    let b_file = write_test_log("t_compare_ignore_crc-b", &[(1, 0, 200, 2), (2, 0, 200, 5)])?;
+    let (_, mut a) = sums_from_file(None, TEST_READ_OPTIONS, a_file.path.clone())?;
+    let (_, b) = sums_from_file(None, TEST_READ_OPTIONS, b_file.path.clone())?;
+
+    let comparison = compare_sums(&a, &b, &TEST_COMPARE_OPTIONS)?;
+    assert_eq!((comparison.num_errors, comparison.num_ignored_crc), (1, 0));
+    let ignore_crc = [Crc(2)];
+    let comparison = compare_sums(
+        &a,
+        &b,
+        &CompareOptions {
+            ignore_crc: &ignore_crc,
+            ..TEST_COMPARE_OPTIONS
+        },
+    )?;
+    assert_eq!((comparison.num_errors, comparison.num_ignored_crc), (0, 1));
+    assert_eq!(comparison.num_same, 1);
+
+    // The repetition of line 2 differs from its first run
+    assert_eq!(report_repetition_errors(&mut a, &[]), 1);
+    assert_eq!(report_repetition_errors(&mut a, &[Crc(6)]), 0);
+    assert_eq!(report_repetition_errors(&mut a, &[Crc(5)]), 0);
+    Ok(())
+}
+
+#[test]
+fn t_compare_gzipped() -> Result<()> {
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+
+    let rows = [(1, 0, 200, 1), (2, 0, 404, 2), (3, 0, 200, 3)];
+    let file = write_test_log("t_compare_gzipped", &rows)?;
+    let gz_file = TestFile::new("t_compare_gzipped", "csv.gz");
+    let mut encoder = GzEncoder::new(
+        std::fs::File::create(&gz_file.path)?,
+        Compression::default(),
+    );
+    encoder.write_all(&std::fs::read(&file.path)?)?;
+    encoder.finish()?;
+    let (_, plain) = sums_from_file(None, TEST_READ_OPTIONS, file.path.clone())?;
+    let (_, gzipped) = sums_from_file(None, TEST_READ_OPTIONS, gz_file.path.clone())?;
+
+    let comparison = compare_sums(&gzipped, &plain, &TEST_COMPARE_OPTIONS)?;
+    assert_eq!(comparison.num_errors, 0);
+    assert_eq!(comparison.num_same, rows.len());
+    Ok(())
+}
+
+#[test]
+fn t_crc_cardinality() -> Result<()> {
+    let summary = |name, rows: &[(u32, u32, u16, u64)]| -> Result<CrcCardinalitySummary> {
+        let file = write_test_log(name, rows)?;
+        let mut crc_cardinality = CrcCardinality::new();
+        for record in open_log_reader(file.path.clone())? {
+            let record = record?;
+            if let LogCsvResult::Ok(LogCsvOk { crc, .. }) = record.result() {
+                crc_cardinality.add(*crc, record.query_reference());
+            }
+        }
+        Ok(crc_cardinality.summary())
+    };
+
+    // 11 queries (one of them repeated), all with the same CRC
+    let same: Vec<_> = (1..=11)
+        .map(|line| (line, 0, 200, 7))
+        .chain([(1, 1, 200, 7)])
+        .collect();
+    let summary_same = summary("t_crc_cardinality-same", &same)?;
+    assert_eq!(
+        (
+            summary_same.num_responses,
+            summary_same.num_distinct_crcs,
+            summary_same.num_distinct_queries,
+            summary_same.most_common
+        ),
+        (12, 1, 11, Some((Crc(7), 12)))
+    );
+    assert_eq!(
+        summary_same.warning.as_deref(),
+        Some(
+            "suspiciously few distinct CRCs (1 for 11 queries), is the endpoint \
+             giving the same response for everything?"
+        )
+    );
+
+    // 10 queries with one CRC is not below the ratio yet
+    let summary_ten = summary(
+        "t_crc_cardinality-ten",
+        &(1..=10).map(|line| (line, 0, 200, 7)).collect::<Vec<_>>(),
+    )?;
+    assert_eq!(summary_ten.warning, None);
+
+    let summary_distinct = summary(
+        "t_crc_cardinality-distinct",
+        &[(1, 0, 200, 1), (2, 0, 200, 2), (3, 0, 200, 2)],
+    )?;
+    assert_eq!(
+        summary_distinct,
+        CrcCardinalitySummary {
+            num_responses: 3,
+            num_distinct_crcs: 2,
+            num_distinct_queries: 3,
+            most_common: Some((Crc(2), 2)),
+            warning: None,
+        }
+    );
+    Ok(())
+}
+
+#[test]
+fn t_check_start_ranges() {
+    let t = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+    let max_skew = Duration::from_secs(10);
+    assert_eq!(
+        check_start_ranges((t(100), t(200)), (t(105), t(210)), max_skew),
+        None
+    );
+    assert!(
+        check_start_ranges((t(100), t(200)), (t(150), t(250)), max_skew)
+            .unwrap()
+            .contains("started 50.00s apart")
+    );
+    assert!(
+        check_start_ranges((t(100), t(200)), (t(300), t(400)), max_skew)
+            .unwrap()
+            .contains("don't overlap")
+    );
+}
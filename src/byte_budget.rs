@@ -0,0 +1,70 @@
+//! Accounting of the bytes of responses that are currently being
+//! received, to allow holding off starting new requests while too
+//! much is in flight.
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use tokio::sync::Notify;
+
+pub struct ByteBudget {
+    cap: usize,
+    used: AtomicUsize,
+    released: Notify,
+}
+
+impl ByteBudget {
+    pub fn new(cap: usize) -> Arc<Self> {
+        Self {
+            cap,
+            used: AtomicUsize::new(0),
+            released: Notify::new(),
+        }
+        .into()
+    }
+
+    /// The number of bytes currently accounted for
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::SeqCst)
+    }
+
+    /// Wait until the accounted bytes are below the cap. Only meant
+    /// to be called from one task (the one starting new requests).
+    pub async fn wait_below_cap(&self) {
+        while self.used() >= self.cap {
+            self.released.notified().await;
+        }
+    }
+
+    /// Returns a guard that releases all the bytes added via it when
+    /// dropped.
+    pub fn guard(self: &Arc<Self>) -> ByteBudgetGuard {
+        ByteBudgetGuard {
+            budget: self.clone(),
+            bytes: 0,
+        }
+    }
+}
+
+pub struct ByteBudgetGuard {
+    budget: Arc<ByteBudget>,
+    bytes: usize,
+}
+
+impl ByteBudgetGuard {
+    /// Account for `n` more bytes. Never blocks, the cap is only
+    /// checked by `ByteBudget::wait_below_cap`.
+    pub fn add(&mut self, n: usize) {
+        self.bytes += n;
+        self.budget.used.fetch_add(n, Ordering::SeqCst);
+    }
+}
+
+impl Drop for ByteBudgetGuard {
+    fn drop(&mut self) {
+        self.budget.used.fetch_sub(self.bytes, Ordering::SeqCst);
+        self.budget.released.notify_one();
+    }
+}
@@ -0,0 +1,90 @@
+//! Normalization of JSON responses before hashing, so that responses
+//! differing only in whitespace or the order of object keys (and
+//! optionally of array items) get the same CRC.
+
+use serde_json::Value;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CanonicalJson {
+    /// Also sort the items of all arrays (by their canonical
+    /// serialization), for endpoints returning results in
+    /// nondeterministic order. Costs a serialization and a sort per
+    /// array level, which may be slow for large, deeply nested
+    /// arrays.
+    pub sort_arrays: bool,
+}
+
+impl CanonicalJson {
+    /// Append the canonical serialization of `value` to `out`: no
+    /// whitespace, and object keys sorted (independently of whether
+    /// serde_json preserves the key order or not).
+    fn write_canonical(self, value: &Value, out: &mut String) {
+        match value {
+            Value::Array(items) => {
+                out.push('[');
+                if self.sort_arrays {
+                    let mut serialized: Vec<String> = items
+                        .iter()
+                        .map(|item| {
+                            let mut s = String::new();
+                            self.write_canonical(item, &mut s);
+                            s
+                        })
+                        .collect();
+                    serialized.sort();
+                    out.push_str(&serialized.join(","));
+                } else {
+                    for (i, item) in items.iter().enumerate() {
+                        if i > 0 {
+                            out.push(',');
+                        }
+                        self.write_canonical(item, out);
+                    }
+                }
+                out.push(']');
+            }
+            Value::Object(map) => {
+                let mut entries: Vec<_> = map.iter().collect();
+                entries.sort_by_key(|(key, _)| *key);
+                out.push('{');
+                for (i, (key, item)) in entries.into_iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    out.push_str(&Value::String(key.clone()).to_string());
+                    out.push(':');
+                    self.write_canonical(item, out);
+                }
+                out.push('}');
+            }
+            Value::Null | Value::Bool(_) | Value::Number(_) | Value::String(_) => {
+                out.push_str(&value.to_string())
+            }
+        }
+    }
+
+    /// The canonical serialization of the JSON document in `body`, or
+    /// an error if it isn't valid JSON.
+    pub fn canonicalize(self, body: &[u8]) -> Result<String, serde_json::Error> {
+        let value: Value = serde_json::from_slice(body)?;
+        let mut out = String::new();
+        self.write_canonical(&value, &mut out);
+        Ok(out)
+    }
+}
+
+#[test]
+fn t_canonicalize() {
+    let body = br#" { "b" : [1, {"y": null, "x": "\u00e9"}, [3, 2]], "a": true } "#;
+    assert_eq!(
+        CanonicalJson::default().canonicalize(body).unwrap(),
+        r#"{"a":true,"b":[1,{"x":"é","y":null},[3,2]]}"#
+    );
+    assert_eq!(
+        CanonicalJson { sort_arrays: true }
+            .canonicalize(body)
+            .unwrap(),
+        r#"{"a":true,"b":[1,[2,3],{"x":"é","y":null}]}"#
+    );
+    assert!(CanonicalJson::default().canonicalize(b"<html>").is_err());
+}
@@ -0,0 +1,130 @@
+//! Classification of hard errors of requests, to tell e.g. a server
+//! that is down from one that is slow or that resets connections
+//! mid-response.
+
+use std::{error::Error, fmt, io, str::FromStr};
+
+use anyhow::{bail, Result};
+
+use crate::run_query::UnexpectedStatus;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ErrorKind {
+    /// Resolving the host name failed
+    Dns,
+    /// Establishing the connection failed
+    Connect,
+    /// The TLS handshake failed
+    Tls,
+    /// Establishing the connection (including the TLS handshake) took
+    /// longer than `--connect-timeout`
+    ConnectTimeout,
+    /// The request timed out
+    Timeout,
+    /// The connection failed after it was established, while sending
+    /// the request or receiving the response
+    Read,
+    /// The response body could not be decoded (e.g. invalid gzip)
+    Decode,
+    /// More redirects than allowed (`--redirects limited:N`,
+    /// `--max-redirects-as-error`)
+    Redirect,
+    /// The response had a different status than expected
+    /// (`--expect-status`, `--status-annotations`)
+    Status,
+    /// Not an error from the HTTP client (e.g. `--max-response-bytes`
+    /// exceeded, or writing the output failed)
+    Other,
+}
+
+impl ErrorKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorKind::Dns => "dns",
+            ErrorKind::Connect => "connect",
+            ErrorKind::Tls => "tls",
+            ErrorKind::ConnectTimeout => "connect-timeout",
+            ErrorKind::Timeout => "timeout",
+            ErrorKind::Read => "read",
+            ErrorKind::Decode => "decode",
+            ErrorKind::Redirect => "redirect",
+            ErrorKind::Status => "status",
+            ErrorKind::Other => "other",
+        }
+    }
+
+    /// Classify `error` by the `reqwest::Error` in its chain, if any.
+    pub fn of(error: &anyhow::Error) -> Self {
+        if error.is::<UnexpectedStatus>() {
+            return ErrorKind::Status;
+        }
+        match error
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<reqwest::Error>())
+        {
+            None => ErrorKind::Other,
+            Some(e) => {
+                if e.is_timeout() && e.is_connect() {
+                    ErrorKind::ConnectTimeout
+                } else if e.is_timeout() {
+                    ErrorKind::Timeout
+                } else if e.is_connect() {
+                    Self::of_connect_error(e)
+                } else if e.is_decode() {
+                    ErrorKind::Decode
+                } else if e.is_redirect() {
+                    ErrorKind::Redirect
+                } else if e.is_body() || e.is_request() {
+                    ErrorKind::Read
+                } else {
+                    ErrorKind::Other
+                }
+            }
+        }
+    }
+
+    /// The underlying errors are only reachable as `source`s, from
+    /// hyper (which says "dns error") and rustls (which reports
+    /// handshake failures as `InvalidData` io errors).
+    fn of_connect_error(error: &reqwest::Error) -> Self {
+        let mut source = error.source();
+        while let Some(cause) = source {
+            if cause.to_string().starts_with("dns error") {
+                return ErrorKind::Dns;
+            }
+            if let Some(io_error) = cause.downcast_ref::<io::Error>() {
+                if io_error.kind() == io::ErrorKind::InvalidData {
+                    return ErrorKind::Tls;
+                }
+            }
+            source = cause.source();
+        }
+        ErrorKind::Connect
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ErrorKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "dns" => ErrorKind::Dns,
+            "connect" => ErrorKind::Connect,
+            "tls" => ErrorKind::Tls,
+            "connect-timeout" => ErrorKind::ConnectTimeout,
+            "timeout" => ErrorKind::Timeout,
+            "read" => ErrorKind::Read,
+            "decode" => ErrorKind::Decode,
+            "redirect" => ErrorKind::Redirect,
+            "status" => ErrorKind::Status,
+            "other" => ErrorKind::Other,
+            _ => bail!("invalid error kind {s:?}"),
+        })
+    }
+}
@@ -0,0 +1,106 @@
+//! Best-effort reporting of completed requests to a Unix domain
+//! socket (`--events-socket`), one JSON object per line, e.g. for a
+//! live dashboard.
+
+use std::{
+    io::{BufWriter, Write},
+    os::unix::net::UnixStream,
+    path::Path,
+    sync::mpsc::{self, TrySendError},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Context, Result};
+use reqwest::StatusCode;
+
+use crate::types::QueryReferenceWithRepetition;
+
+/// How many events may be queued for the writer thread; further
+/// events are dropped while the consumer is not keeping up.
+const CHANNEL_CAPACITY: usize = 10_000;
+
+/// A completed request
+pub struct Event {
+    /// When the request finished
+    pub timestamp: SystemTime,
+    pub reference: QueryReferenceWithRepetition,
+    /// `None` for hard errors
+    pub status: Option<StatusCode>,
+    pub duration: Duration,
+    /// Size of the response body
+    pub bytes: usize,
+}
+
+impl Event {
+    fn to_line(&self) -> String {
+        let Self {
+            timestamp,
+            reference,
+            status,
+            duration,
+            bytes,
+        } = self;
+        let mut line = serde_json::json!({
+            "t": timestamp
+                .duration_since(UNIX_EPOCH)
+                .map_or(0., |d| d.as_secs_f64()),
+            "line": u64::from(reference.query_reference.query_index) + 1,
+            "rep": reference.repetition,
+            "status": status.map(|status| status.as_u16()),
+            "d": duration.as_secs_f64(),
+            "bytes": bytes,
+        })
+        .to_string();
+        line.push('\n');
+        line
+    }
+}
+
+/// Writes events to a Unix domain socket from a separate thread. If
+/// the consumer disconnects or can't keep up, events are dropped
+/// instead of affecting the run.
+pub struct EventSink {
+    channel_tx: mpsc::SyncSender<String>,
+}
+
+impl EventSink {
+    /// Connect to the socket at `path` (which the consumer must be
+    /// listening on).
+    pub fn connect(path: &Path) -> Result<Self> {
+        let stream = UnixStream::connect(path)
+            .with_context(|| anyhow!("connecting to events socket {path:?}"))?;
+        let (channel_tx, channel_rx) = mpsc::sync_channel::<String>(CHANNEL_CAPACITY);
+        let path = path.to_owned();
+        thread::spawn(move || {
+            let mut out = BufWriter::new(stream);
+            while let Ok(line) = channel_rx.recv() {
+                let mut result = out.write_all(line.as_bytes());
+                // Write whatever else is queued before flushing, to
+                // batch bursts of events
+                while result.is_ok() {
+                    match channel_rx.try_recv() {
+                        Ok(line) => result = out.write_all(line.as_bytes()),
+                        Err(_) => break,
+                    }
+                }
+                if let Err(e) = result.and_then(|()| out.flush()) {
+                    eprintln!(
+                        "api-query: events socket {path:?}: {e}; \
+                         no further events will be sent"
+                    );
+                    // Dropping the receiver makes `send` a no-op
+                    return;
+                }
+            }
+        });
+        Ok(Self { channel_tx })
+    }
+
+    /// Queue the event for writing, dropping it if the queue is full
+    /// or the socket failed.
+    pub fn send(&self, event: &Event) {
+        // Best effort, see `EventSink`
+        let _: Result<(), TrySendError<_>> = self.channel_tx.try_send(event.to_line());
+    }
+}
@@ -0,0 +1,66 @@
+//! Wrapping of plain GraphQL queries into the JSON request envelope
+//! (`{"query": ..., "variables": ...}`).
+
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use serde_json::{Map, Value};
+
+#[derive(Debug, Default)]
+pub struct GraphqlEnvelope {
+    variables: Option<Value>,
+}
+
+impl GraphqlEnvelope {
+    /// Without variables
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// With the variables from the JSON file at `path`, which have to
+    /// be a JSON object.
+    pub fn with_variables_from_path(path: &Path) -> Result<Self> {
+        let s = std::fs::read_to_string(path)
+            .with_context(|| anyhow!("reading GraphQL variables file {path:?}"))?;
+        let variables: Value = serde_json::from_str(&s)
+            .with_context(|| anyhow!("parsing GraphQL variables file {path:?} as JSON"))?;
+        if !variables.is_object() {
+            return Err(anyhow!(
+                "GraphQL variables file {path:?} must contain a JSON object"
+            ));
+        }
+        Ok(Self {
+            variables: Some(variables),
+        })
+    }
+
+    /// The JSON request body for `query`
+    pub fn wrap(&self, query: &str) -> String {
+        let mut envelope = Map::new();
+        envelope.insert("query".into(), query.into());
+        if let Some(variables) = &self.variables {
+            envelope.insert("variables".into(), variables.clone());
+        }
+        Value::Object(envelope).to_string()
+    }
+}
+
+#[test]
+fn t_wrap() -> Result<()> {
+    let query = "query {\n  hosts(name: \"a\\\\b\") {\n    id\n  }\n}";
+    let body: Value = serde_json::from_str(&GraphqlEnvelope::new().wrap(query))?;
+    assert_eq!(body["query"], query);
+    assert!(body.get("variables").is_none());
+
+    let path = std::env::temp_dir().join(format!("api-query-t_wrap-{}.json", std::process::id()));
+    std::fs::write(&path, r#"{"name": "a\"b"}"#)?;
+    let envelope = GraphqlEnvelope::with_variables_from_path(&path);
+    std::fs::write(&path, "[1]")?;
+    let not_object = GraphqlEnvelope::with_variables_from_path(&path);
+    std::fs::remove_file(&path)?;
+    let body: Value = serde_json::from_str(&envelope?.wrap(query))?;
+    assert_eq!(body["query"], query);
+    assert_eq!(body["variables"], serde_json::json!({"name": "a\"b"}));
+    assert!(not_object.is_err());
+    Ok(())
+}
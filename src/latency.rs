@@ -0,0 +1,91 @@
+//! Collection of request durations, for latency statistics and
+//! percentiles.
+
+use std::time::Duration;
+
+use crate::percentiles::Percentiles;
+
+/// Request durations, kept exactly (8 bytes per request).
+#[derive(Debug, Clone)]
+pub struct Latencies {
+    /// In seconds
+    seconds: Percentiles,
+}
+
+impl Default for Latencies {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Rounded to microseconds, to not show float artifacts
+fn duration(seconds: f64) -> Duration {
+    Duration::from_micros((seconds * 1e6).round() as u64)
+}
+
+impl Latencies {
+    /// The memory taken per request (not counting spare capacity)
+    pub const BYTES_PER_REQUEST: usize = size_of::<f64>();
+
+    pub fn new() -> Self {
+        Self {
+            seconds: Percentiles::exact(),
+        }
+    }
+
+    pub fn add(&mut self, duration: Duration) {
+        self.seconds.add(duration.as_secs_f64());
+    }
+
+    pub fn len(&self) -> usize {
+        self.seconds.len() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seconds.is_empty()
+    }
+
+    /// Zero if empty (also for the other statistics)
+    pub fn mean(&self) -> Duration {
+        duration(self.seconds.mean())
+    }
+
+    /// Population standard deviation
+    pub fn stdev(&self) -> Duration {
+        duration(self.seconds.stdev())
+    }
+
+    pub fn max(&self) -> Duration {
+        duration(self.seconds.max())
+    }
+
+    /// The fraction (0..=1) of the durations that lie within
+    /// `low..=high`
+    pub fn fraction_within(&self, low: Duration, high: Duration) -> f64 {
+        self.seconds
+            .fraction_within(low.as_secs_f64(), high.as_secs_f64())
+    }
+
+    /// The duration that `q` (0..=1) of the requests took at most
+    /// (nearest-rank method).
+    pub fn percentile(&mut self, q: f64) -> Duration {
+        duration(self.seconds.quantile(q))
+    }
+}
+
+#[test]
+fn t_latencies() {
+    let mut latencies = Latencies::new();
+    assert_eq!(latencies.percentile(0.99), Duration::ZERO);
+    for ms in (1..=100).rev() {
+        latencies.add(Duration::from_millis(ms));
+    }
+    assert_eq!(latencies.len(), 100);
+    assert_eq!(latencies.mean(), Duration::from_micros(50_500));
+    assert_eq!(latencies.max(), Duration::from_millis(100));
+    assert_eq!(latencies.percentile(0.5), Duration::from_millis(50));
+    assert_eq!(latencies.percentile(0.99), Duration::from_millis(99));
+    assert_eq!(latencies.percentile(1.), Duration::from_millis(100));
+    assert_eq!(latencies.percentile(0.), Duration::from_millis(1));
+    assert_eq!(latencies.stdev().as_micros(), 28_866);
+}
@@ -1,10 +1,25 @@
 pub mod auto_vec;
+pub mod byte_budget;
+pub mod canonical_json;
 pub mod clone;
 pub mod cowstr;
+pub mod error_kind;
+pub mod events;
 pub mod get_terminal_width;
+pub mod graphql;
+pub mod latency;
 pub mod log_csv;
+pub mod log_sums;
 pub mod my_crc;
+pub mod percentiles;
+pub mod redact;
+pub mod response_schema;
+pub mod run_query;
+pub mod summary;
+pub mod template;
 pub mod time;
+pub mod transform;
 pub mod types;
 pub mod util;
 pub mod vec_backing;
+pub mod vegeta;
@@ -1,37 +1,65 @@
 use std::{
     borrow::Cow,
+    collections::VecDeque,
     convert::TryInto,
+    fmt,
     fs::File,
-    io::{BufReader, BufWriter},
+    io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom},
     marker::PhantomData,
-    path::Path,
+    path::{Path, PathBuf},
     sync::{
-        mpsc::{self, SendError},
+        mpsc::{self, RecvTimeoutError, SendError},
         Arc,
     },
     thread,
+    time::{Duration, Instant},
 };
 
 use anyhow::{anyhow, bail, Context, Result};
+use flate2::read::MultiGzDecoder;
 use reqwest::StatusCode;
 
 use crate::{
     cowstr::Cowstr,
+    error_kind::ErrorKind,
     my_crc::Crc,
     time::UnixTimeWrap,
     types::{Queries, QueryReference, QueryReferenceWithRepetition},
     vec_backing::RefVecBacking,
 };
 
+/// The logged info about a query that got a response
+#[derive(Debug, Clone)]
+pub struct LogCsvOk {
+    pub status: StatusCode,
+    /// The length of the response body
+    pub length: usize,
+    pub crc: Crc,
+    /// The value of the `Content-Type` header, if present
+    pub content_type: Option<String>,
+    /// The time to the first byte of the body in seconds (missing in
+    /// older log files)
+    pub ttfb: Option<f64>,
+    /// The CRC of the request body, if `--crc-request` was given
+    pub request_crc: Option<Crc>,
+    /// How many times the request was sent (more than once with
+    /// `--retry-status`; missing in older log files)
+    pub attempts: Option<u32>,
+    /// The number of lines of the response with `--ndjson`
+    pub ndjson_lines: Option<u64>,
+}
+
 /// The result of a query
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum LogCsvResult {
-    Ok(StatusCode, usize, Crc),
-    Err(String),
+    Ok(LogCsvOk),
+    /// The kind of error (missing in older log files), and the error
+    /// message
+    Err(Option<ErrorKind>, String),
 }
 
 /// A log entry
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct LogCsvRecord(
     /// Reference (line number) into the queries file
     pub QueryReference,
@@ -43,7 +71,8 @@ pub struct LogCsvRecord(
     pub UnixTimeWrap,
     /// The time difference
     pub f64,
-    /// LogCsvResult is yielding 4 columns in the CSV file
+    /// LogCsvResult is yielding the remaining columns in the CSV
+    /// file (see `LogCsvNormalFormat::HEADER`)
     pub LogCsvResult,
 );
 
@@ -67,15 +96,31 @@ impl LogCsvRecord {
     /// the status!
     pub fn crc(&self) -> Option<Crc> {
         match self.result() {
-            LogCsvResult::Ok(_status_code, _length, crc) => Some(*crc),
-            LogCsvResult::Err(_) => None,
+            LogCsvResult::Ok(ok) => Some(ok.crc),
+            LogCsvResult::Err(..) => None,
+        }
+    }
+    /// The CRC of the request body, if it was logged (non-error
+    /// cases).
+    pub fn request_crc(&self) -> Option<Crc> {
+        match self.result() {
+            LogCsvResult::Ok(ok) => ok.request_crc,
+            LogCsvResult::Err(..) => None,
         }
     }
     /// The response info when there is one (non-error cases).
     pub fn status_length_crc(&self) -> Option<(StatusCode, usize, Crc)> {
         match self.result() {
-            LogCsvResult::Ok(status_code, length, crc) => Some((*status_code, *length, *crc)),
-            LogCsvResult::Err(_) => None,
+            LogCsvResult::Ok(ok) => Some((ok.status, ok.length, ok.crc)),
+            LogCsvResult::Err(..) => None,
+        }
+    }
+    /// How many times the request was sent, if logged (non-error
+    /// cases).
+    pub fn attempts(&self) -> Option<u32> {
+        match self.result() {
+            LogCsvResult::Ok(ok) => ok.attempts,
+            LogCsvResult::Err(..) => None,
         }
     }
 }
@@ -119,7 +164,10 @@ impl Format for LogCsvNormalFormat {
 }
 
 impl LogCsvNormalFormat {
-    const NUM_COLS: usize = 10;
+    const NUM_COLS: usize = 16;
+    /// Log files written by older versions lack the columns after
+    /// this many; those are read as empty.
+    const MIN_NUM_COLS: usize = 10;
     const HEADER: [&str; Self::NUM_COLS] = [
         "line in query file",
         "repetition",
@@ -131,10 +179,17 @@ impl LogCsvNormalFormat {
         "length",
         "crc",
         "error",
+        "content type",
+        "ttfb",
+        "request crc",
+        "attempts",
+        "error kind",
+        "ndjson lines",
     ];
 
     pub fn parse_row(row: &[impl AsRef<str>; Self::NUM_COLS]) -> Result<LogCsvRecord> {
-        let [line, repetition, start, end, d, ok_err, status_code, length, crc, error] = row;
+        let [line, repetition, start, end, d, ok_err, status_code, length, crc, error, content_type, ttfb, request_crc, attempts, error_kind, ndjson_lines] =
+            row;
 
         macro_rules! let_parse {
         { $var:ident ? $msg:expr } =>  {
@@ -165,6 +220,36 @@ impl LogCsvNormalFormat {
                 let_parse!(status_code ? "HTTP status code");
                 let_parse!(length ? "length");
                 let_parse!(crc ? "CRC");
+                let content_type = content_type.as_ref();
+                let content_type = if content_type.is_empty() {
+                    None
+                } else {
+                    Some(content_type.to_owned())
+                };
+                let ttfb = if ttfb.as_ref().is_empty() {
+                    None
+                } else {
+                    let_parse!(ttfb ? "ttfb");
+                    Some(ttfb)
+                };
+                let request_crc = if request_crc.as_ref().is_empty() {
+                    None
+                } else {
+                    let_parse!(request_crc ? "request CRC");
+                    Some(request_crc)
+                };
+                let attempts = if attempts.as_ref().is_empty() {
+                    None
+                } else {
+                    let_parse!(attempts ? "attempts");
+                    Some(attempts)
+                };
+                let ndjson_lines = if ndjson_lines.as_ref().is_empty() {
+                    None
+                } else {
+                    let_parse!(ndjson_lines ? "ndjson lines");
+                    Some(ndjson_lines)
+                };
 
                 Ok(LogCsvRecord(
                     line,
@@ -172,42 +257,141 @@ impl LogCsvNormalFormat {
                     start,
                     end,
                     d,
-                    LogCsvResult::Ok(status_code, length, crc),
+                    LogCsvResult::Ok(LogCsvOk {
+                        status: status_code,
+                        length,
+                        crc,
+                        content_type,
+                        ttfb,
+                        request_crc,
+                        attempts,
+                        ndjson_lines,
+                    }),
+                ))
+            }
+            "Err" => {
+                let error_kind = if error_kind.as_ref().is_empty() {
+                    None
+                } else {
+                    let_parse!(error_kind ? "error kind");
+                    Some(error_kind)
+                };
+                Ok(LogCsvRecord(
+                    line,
+                    repetition,
+                    start,
+                    end,
+                    d,
+                    LogCsvResult::Err(error_kind, error.as_ref().to_owned()),
                 ))
             }
-            "Err" => Ok(LogCsvRecord(
-                line,
-                repetition,
-                start,
-                end,
-                d,
-                LogCsvResult::Err(error.as_ref().to_owned()),
-            )),
             _ => bail!("invalid entry in 'Ok/Err' column: {ok_err:?}"),
         }
     }
 }
 
+/// Context attached to errors about a row in a log file that could
+/// not be parsed, as opposed to errors reading the file; the readers
+/// continue with the next row after yielding such an error, thus
+/// callers can choose to skip them (check with
+/// `error.is::<BadRow>()`).
+#[derive(Debug)]
+pub struct BadRow {
+    /// Where the row is in the file
+    pub location: String,
+}
+
+impl fmt::Display for BadRow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "bad row at {}", self.location)
+    }
+}
+
+/// Convert an error from reading a record with the csv crate: errors
+/// about the record itself (like an invalid number of fields or
+/// invalid UTF-8) get the `BadRow` context.
+fn csv_read_error(
+    error: csv::Error,
+    path: &Path,
+    location: impl FnOnce() -> String,
+) -> anyhow::Error {
+    if error.is_io_error() {
+        anyhow::Error::from(error).context(anyhow!("reading from {path:?}"))
+    } else {
+        anyhow::Error::from(error).context(BadRow {
+            location: location(),
+        })
+    }
+}
+
+/// Parse a row read from a log file. `location` describes where the
+/// row is in the file, for error messages.
+fn parse_stringrecord(
+    stringrecord: &csv::StringRecord,
+    fields: &mut RefVecBacking<'static, str>,
+    location: impl FnOnce() -> String,
+) -> Result<LogCsvRecord> {
+    type Format = LogCsvNormalFormat;
+
+    let mut fields = fields.borrow_mut();
+    for field in stringrecord {
+        fields.push(field);
+    }
+    let num_fields = fields.as_slice().len();
+    if (Format::MIN_NUM_COLS..Format::NUM_COLS).contains(&num_fields) {
+        for _ in num_fields..Format::NUM_COLS {
+            fields.push("");
+        }
+    }
+    let sl = fields.as_slice();
+    match sl.try_into() {
+        Ok(arf) => Format::parse_row(arf),
+        Err(_) => Err(anyhow!(
+            "invalid number of columns: expected {}, got {}",
+            Format::NUM_COLS,
+            num_fields,
+        )),
+    }
+    .with_context(|| BadRow {
+        location: location(),
+    })
+}
+
 /// Iterator to read back a log file written by the LogCsv writer
 pub struct LogCsvReader {
     path: Arc<Path>,
-    line0: usize,
-    reader: csv::Reader<BufReader<File>>,
+    reader: csv::Reader<Box<dyn Read + Send>>,
     stringrecord: csv::StringRecord,
     fields: RefVecBacking<'static, str>,
 }
 
-impl LogCsvReader {
-    // type Format = LogCsvNormalFormat; -- unstable, see inside `next()` instead
+/// The magic bytes at the start of gzip files
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
 
+/// Whether the file at `path` is gzip-compressed (checked via its
+/// first bytes, not its name).
+fn is_gzipped(path: &Path) -> Result<bool> {
+    let mut magic = [0; 2];
+    let mut file = File::open(path).with_context(|| anyhow!("opening {path:?} for reading"))?;
+    match file.read_exact(&mut magic) {
+        Ok(()) => Ok(magic == GZIP_MAGIC),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e).with_context(|| anyhow!("reading from {path:?}")),
+    }
+}
+
+impl LogCsvReader {
+    /// Gzip-compressed files are decompressed transparently.
     pub fn open(path: Arc<Path>) -> Result<Self> {
-        let log_file = BufReader::new(
-            File::open(&*path).with_context(|| anyhow!("opening {path:?} for reading"))?,
-        );
+        let file = File::open(&*path).with_context(|| anyhow!("opening {path:?} for reading"))?;
+        let log_file: Box<dyn Read + Send> = if is_gzipped(&path)? {
+            Box::new(MultiGzDecoder::new(BufReader::new(file)))
+        } else {
+            Box::new(BufReader::new(file))
+        };
         let reader = csv::Reader::from_reader(log_file);
         Ok(Self {
             path,
-            line0: 0,
             reader,
             stringrecord: csv::StringRecord::new(),
             fields: RefVecBacking::new(),
@@ -219,33 +403,278 @@ impl Iterator for LogCsvReader {
     type Item = Result<LogCsvRecord>;
 
     fn next(&mut self) -> Option<Result<LogCsvRecord>> {
-        type Format = LogCsvNormalFormat;
-
-        match self
-            .reader
-            .read_record(&mut self.stringrecord)
-            .with_context(|| anyhow!(""))
-        {
+        match self.reader.read_record(&mut self.stringrecord) {
             Ok(true) => {
-                let mut fields = self.fields.borrow_mut();
-                for field in &self.stringrecord {
-                    fields.push(field);
+                let Self {
+                    path,
+                    stringrecord,
+                    fields,
+                    ..
+                } = self;
+                Some(parse_stringrecord(stringrecord, fields, || {
+                    let line = stringrecord
+                        .position()
+                        .map_or(0, |position| position.line());
+                    format!("{path:?}:{line}")
+                }))
+            }
+            Ok(false) => None,
+            Err(e) => {
+                let line = e.position().map_or(0, |position| position.line());
+                Some(Err(csv_read_error(e, &self.path, || {
+                    format!("{:?}:{line}", self.path)
+                })))
+            }
+        }
+    }
+}
+
+/// Metadata about a run, stored as JSON in a file next to the log
+/// file (`run.csv` -> `run.csv.meta.json`), since the CSV format has
+/// no place for it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LogMeta {
+    /// From `--run-label`
+    pub run_label: Option<String>,
+    /// From `--hash-prefix-bytes`: the CRCs only cover this many
+    /// bytes at the start of the responses
+    pub hash_prefix_bytes: Option<u64>,
+    /// From `--ndjson`: the CRCs are folded over the lines
+    pub ndjson: bool,
+}
+
+impl LogMeta {
+    pub fn path(log_path: &Path) -> PathBuf {
+        let mut path = log_path.as_os_str().to_owned();
+        path.push(".meta.json");
+        path.into()
+    }
+
+    /// Write the metadata file for the log at `log_path`, or, if there
+    /// is no metadata, remove a stale one from a previous run.
+    pub fn write(&self, log_path: &Path) -> Result<()> {
+        let path = Self::path(log_path);
+        if *self == Self::default() {
+            match std::fs::remove_file(&path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                    Err(e).with_context(|| anyhow!("removing {path:?}"))
                 }
-                let sl = fields.as_slice();
-                match sl.try_into() {
-                    Ok(arf) => Some(Format::parse_row(arf)),
-                    Err(_) => Some(Err(anyhow!(
-                        "invalid number of columns: expected {}, got {} at {:?}:{}",
-                        Format::NUM_COLS,
-                        sl.len(),
-                        self.path,
-                        self.line0 + 1
-                    ))),
+                _ => Ok(()),
+            }
+        } else {
+            let Self {
+                run_label,
+                hash_prefix_bytes,
+                ndjson,
+            } = self;
+            let json = serde_json::json!({
+                "run-label": run_label,
+                "hash-prefix-bytes": hash_prefix_bytes,
+                "ndjson": ndjson,
+            });
+            std::fs::write(&path, format!("{json:#}\n"))
+                .with_context(|| anyhow!("writing {path:?}"))
+        }
+    }
+
+    /// The metadata of the log at `log_path`, empty if there is no
+    /// metadata file.
+    pub fn read(log_path: &Path) -> Result<Self> {
+        let path = Self::path(log_path);
+        let string = match std::fs::read_to_string(&path) {
+            Ok(string) => string,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e).with_context(|| anyhow!("reading {path:?}")),
+        };
+        let json: serde_json::Value =
+            serde_json::from_str(&string).with_context(|| anyhow!("parsing {path:?}"))?;
+        Ok(Self {
+            run_label: json["run-label"].as_str().map(String::from),
+            hash_prefix_bytes: json["hash-prefix-bytes"].as_u64(),
+            ndjson: json["ndjson"].as_bool().unwrap_or(false),
+        })
+    }
+}
+
+/// Logs smaller than this are read serially by `open_log_reader`.
+const PARALLEL_MIN_FILE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// The approximate size of the chunks that `ParallelLogCsvReader`
+/// parses in separate threads, as used by `open_log_reader`.
+const PARALLEL_CHUNK_SIZE: u64 = 16 * 1024 * 1024;
+
+/// The byte offsets and line numbers where the records of a log file
+/// start that come first after the header and after each further
+/// `chunk_size` bytes, plus the file size and line count. Needs to
+/// read through the whole file, tracking quoting (fields can contain
+/// newlines), but that is much faster than parsing it. Lines are
+/// counted like the csv crate does, i.e. including the newlines in
+/// quoted fields.
+fn chunk_boundaries(path: &Path, chunk_size: u64) -> Result<Vec<(u64, u64)>> {
+    let mut reader = BufReader::with_capacity(
+        1024 * 1024,
+        File::open(path).with_context(|| anyhow!("opening {path:?} for reading"))?,
+    );
+    let mut boundaries = Vec::new();
+    let mut in_quotes = false;
+    let mut position: u64 = 0;
+    let mut line: u64 = 1;
+    let mut next_boundary: u64 = 0;
+    loop {
+        let buf = reader
+            .fill_buf()
+            .with_context(|| anyhow!("reading from {path:?}"))?;
+        if buf.is_empty() {
+            break;
+        }
+        for (i, &b) in buf.iter().enumerate() {
+            match b {
+                b'"' => in_quotes = !in_quotes,
+                b'\n' => {
+                    line += 1;
+                    let record_start = position + i as u64 + 1;
+                    if !in_quotes && record_start > next_boundary {
+                        boundaries.push((record_start, line));
+                        next_boundary = record_start + chunk_size;
+                    }
                 }
+                _ => (),
             }
-            Ok(false) => None,
-            Err(e) => Some(Err(e)),
         }
+        let len = buf.len();
+        position += len as u64;
+        reader.consume(len);
+    }
+    if boundaries.last().map(|(start, _)| *start) != Some(position) {
+        boundaries.push((position, line));
+    }
+    Ok(boundaries)
+}
+
+/// Parse the records in the given byte range of a log file, which
+/// starts at line `start_line`.
+fn parse_chunk(
+    path: &Path,
+    start: u64,
+    start_line: u64,
+    end: u64,
+) -> Result<Vec<Result<LogCsvRecord>>> {
+    let mut file = File::open(path).with_context(|| anyhow!("opening {path:?} for reading"))?;
+    file.seek(SeekFrom::Start(start))
+        .with_context(|| anyhow!("seeking in {path:?}"))?;
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .from_reader(BufReader::new(file.take(end - start)));
+    let mut stringrecord = csv::StringRecord::new();
+    let mut fields = RefVecBacking::new();
+    let mut records = Vec::new();
+    let location = |position: Option<&csv::Position>| {
+        let line = position.map_or(0, |position| start_line + position.line() - 1);
+        format!("{path:?}:{line}")
+    };
+    loop {
+        match reader.read_record(&mut stringrecord) {
+            Ok(true) => records.push(parse_stringrecord(&stringrecord, &mut fields, || {
+                location(stringrecord.position())
+            })),
+            Ok(false) => break,
+            Err(e) => {
+                if e.is_io_error() {
+                    return Err(e).with_context(|| anyhow!("reading from {path:?}"));
+                }
+                let position = e.position().cloned();
+                records.push(Err(csv_read_error(e, path, || location(position.as_ref()))));
+            }
+        }
+    }
+    Ok(records)
+}
+
+type ChunkResult = Result<Vec<Result<LogCsvRecord>>>;
+
+/// Like `LogCsvReader`, but parses chunks of the file in separate
+/// threads (yielding the records still in file order).
+pub struct ParallelLogCsvReader {
+    path: Arc<Path>,
+    /// Byte ranges, and starting lines, of the chunks not yet started
+    chunks: std::vec::IntoIter<(u64, u64, u64)>,
+    /// The chunks being parsed, in file order
+    running: VecDeque<thread::JoinHandle<ChunkResult>>,
+    /// The records from the current chunk
+    current: std::vec::IntoIter<Result<LogCsvRecord>>,
+    num_threads: usize,
+}
+
+impl ParallelLogCsvReader {
+    /// Parse chunks of about `chunk_size` bytes, in up to
+    /// `num_threads` threads at a time.
+    pub fn open(path: Arc<Path>, num_threads: usize, chunk_size: u64) -> Result<Self> {
+        let boundaries = chunk_boundaries(&path, chunk_size)?;
+        let chunks: Vec<(u64, u64, u64)> = boundaries
+            .windows(2)
+            .map(|window| (window[0].0, window[0].1, window[1].0))
+            .collect();
+        Ok(Self {
+            path,
+            chunks: chunks.into_iter(),
+            running: VecDeque::new(),
+            current: Vec::new().into_iter(),
+            num_threads: num_threads.max(1),
+        })
+    }
+}
+
+impl Iterator for ParallelLogCsvReader {
+    type Item = Result<LogCsvRecord>;
+
+    fn next(&mut self) -> Option<Result<LogCsvRecord>> {
+        loop {
+            if let Some(record) = self.current.next() {
+                return Some(record);
+            }
+            while self.running.len() < self.num_threads {
+                if let Some((start, start_line, end)) = self.chunks.next() {
+                    let path = self.path.clone();
+                    self.running.push_back(thread::spawn(move || {
+                        parse_chunk(&path, start, start_line, end)
+                    }));
+                } else {
+                    break;
+                }
+            }
+            let thread = self.running.pop_front()?;
+            match thread.join() {
+                Ok(Ok(records)) => self.current = records.into_iter(),
+                Ok(Err(e)) => return Some(Err(e)),
+                Err(e) => {
+                    return Some(Err(anyhow!(
+                        "parser thread for file {:?} panicked: {e:?}",
+                        self.path
+                    )))
+                }
+            }
+        }
+    }
+}
+
+/// Open a log file for reading, with `ParallelLogCsvReader` if the
+/// file is large and there are multiple CPUs (and it is not
+/// compressed), `LogCsvReader` otherwise.
+pub fn open_log_reader(
+    path: Arc<Path>,
+) -> Result<Box<dyn Iterator<Item = Result<LogCsvRecord>> + Send>> {
+    let size = std::fs::metadata(&*path)
+        .with_context(|| anyhow!("getting metadata of {path:?}"))?
+        .len();
+    let num_threads = thread::available_parallelism().map_or(1, |n| n.get());
+    if size >= PARALLEL_MIN_FILE_SIZE && num_threads > 1 && !is_gzipped(&path)? {
+        Ok(Box::new(ParallelLogCsvReader::open(
+            path,
+            num_threads,
+            PARALLEL_CHUNK_SIZE,
+        )?))
+    } else {
+        Ok(Box::new(LogCsvReader::open(path)?))
     }
 }
 
@@ -253,6 +682,8 @@ impl Iterator for LogCsvReader {
 struct LogCsv<F: Format> {
     path: Arc<Path>,
     writer: csv::Writer<BufWriter<File>>,
+    /// Handle to the same file as in `writer`, for syncing
+    file: File,
     format: F,
 }
 
@@ -270,6 +701,9 @@ impl<F: Format> LogCsv<F> {
             .open(&*path)
             .with_context(|| anyhow!("opening {path:?} for writing"))?;
 
+        let sync_file = file
+            .try_clone()
+            .with_context(|| anyhow!("cloning file handle for {path:?}"))?;
         let log_file = BufWriter::new(file);
 
         let mut writer = csv::Writer::from_writer(log_file);
@@ -280,6 +714,7 @@ impl<F: Format> LogCsv<F> {
         Ok(Self {
             path,
             writer,
+            file: sync_file,
             format,
         })
     }
@@ -288,11 +723,12 @@ impl<F: Format> LogCsv<F> {
         let Self {
             path,
             writer,
+            file: _,
             format,
         } = self;
 
         let LogCsvRecord(a, b, c, d, e, res) = values;
-        let mut record: [Cowstr; 11] = [
+        let mut record: [Cowstr; 17] = [
             a.to_string().into(),
             b.to_string().into(),
             c.to_string().into(),
@@ -303,28 +739,61 @@ impl<F: Format> LogCsv<F> {
             "".into(),
             "".into(),
             "".into(),
-            // only used if `queries` was given
             "".into(), // index 10
+            "".into(),
+            "".into(),
+            "".into(), // index 13
+            "".into(),
+            "".into(), // index 15
+            // only used if `queries` was given
+            "".into(), // index 16
         ];
         match res {
-            LogCsvResult::Ok(status_code, length, crc) => {
+            LogCsvResult::Ok(LogCsvOk {
+                status,
+                length,
+                crc,
+                content_type,
+                ttfb,
+                request_crc,
+                attempts,
+                ndjson_lines,
+            }) => {
                 record[5] = "Ok".into();
-                record[6] = status_code.to_string().into();
+                record[6] = status.to_string().into();
                 record[7] = length.to_string().into();
                 record[8] = crc.to_string().into();
+                if let Some(content_type) = content_type {
+                    record[10] = content_type.into();
+                }
+                if let Some(ttfb) = ttfb {
+                    record[11] = ttfb.to_string().into();
+                }
+                if let Some(request_crc) = request_crc {
+                    record[12] = request_crc.to_string().into();
+                }
+                if let Some(attempts) = attempts {
+                    record[13] = attempts.to_string().into();
+                }
+                if let Some(ndjson_lines) = ndjson_lines {
+                    record[15] = ndjson_lines.to_string().into();
+                }
             }
-            LogCsvResult::Err(e) => {
+            LogCsvResult::Err(error_kind, e) => {
                 record[5] = "Err".into();
                 record[9] = e.into();
+                if let Some(error_kind) = error_kind {
+                    record[14] = error_kind.as_str().into();
+                }
             }
         }
         let record_used = if let Some(queries) = format.queries() {
-            record[10] = queries.borrow_queries()[a.query_index_usize()]
+            record[16] = queries.borrow_queries()[a.query_index_usize()]
                 .string
                 .into();
             &record
         } else {
-            &record[..10]
+            &record[..16]
         };
 
         writer
@@ -340,25 +809,91 @@ impl<F: Format> LogCsv<F> {
             .with_context(|| anyhow!("flushing CSV log file {:?}", self.path))?;
         Ok(())
     }
+
+    /// Flush, then have the OS write the file to disk.
+    fn sync(&mut self) -> Result<()> {
+        self.flush()?;
+        self.file
+            .sync_all()
+            .with_context(|| anyhow!("syncing CSV log file {:?} to disk", self.path))?;
+        Ok(())
+    }
+
+    /// Handle a message from `LogCsvWriter`; returns whether there is
+    /// now unflushed data.
+    fn handle(&mut self, message: WriterMessage) -> Result<bool> {
+        match message {
+            WriterMessage::Record(record) => {
+                self.write_row(record)?;
+                Ok(true)
+            }
+            WriterMessage::Checkpoint => {
+                self.sync()?;
+                Ok(false)
+            }
+        }
+    }
 }
 
+enum WriterMessage {
+    Record(LogCsvRecord),
+    Checkpoint,
+}
+
+/// The default for the `channel_capacity` of `LogCsvWriter::create`
+pub const DEFAULT_CHANNEL_CAPACITY: usize = 100_000;
+
 /// Log writer in a separate thread, with the writing end of a channel
 /// for sending it log messages.
 pub struct LogCsvWriter<F: Format> {
     thread: thread::JoinHandle<Result<()>>,
     _format: PhantomData<fn() -> F>,
-    channel_tx: mpsc::Sender<LogCsvRecord>,
+    channel_tx: mpsc::SyncSender<WriterMessage>,
     path: Arc<Path>,
 }
 
 impl<F: Format + Send + 'static> LogCsvWriter<F> {
-    /// Create a log writer running in a separate thread.
-    pub fn create(path: Arc<Path>, overwrite: bool, format: F) -> Result<Self> {
+    /// Create a log writer running in a separate thread. If
+    /// `flush_interval` is given, the file is flushed at least that
+    /// often while records are coming in (e.g. so that the log can be
+    /// followed with `tail -f`), otherwise only when the buffer is
+    /// full and on `finish()`. At most `channel_capacity` records
+    /// are queued for the thread, `send` blocks when that many are
+    /// pending.
+    pub fn create(
+        path: Arc<Path>,
+        overwrite: bool,
+        format: F,
+        flush_interval: Option<Duration>,
+        channel_capacity: usize,
+    ) -> Result<Self> {
         let mut log_file = LogCsv::create(path.clone(), overwrite, format)?;
-        let (channel_tx, channel_rx) = mpsc::channel();
+        let (channel_tx, channel_rx) = mpsc::sync_channel(channel_capacity);
         let thread = thread::spawn(move || -> Result<()> {
-            for entry in channel_rx {
-                log_file.write_row(entry)?;
+            if let Some(flush_interval) = flush_interval {
+                let mut last_flush = Instant::now();
+                let mut dirty = false;
+                loop {
+                    let timeout = flush_interval.saturating_sub(last_flush.elapsed());
+                    match channel_rx.recv_timeout(timeout) {
+                        Ok(message) => {
+                            dirty = log_file.handle(message)?;
+                        }
+                        Err(RecvTimeoutError::Timeout) => (),
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    }
+                    if last_flush.elapsed() >= flush_interval {
+                        if dirty {
+                            log_file.flush()?;
+                            dirty = false;
+                        }
+                        last_flush = Instant::now();
+                    }
+                }
+            } else {
+                for message in channel_rx {
+                    log_file.handle(message)?;
+                }
             }
             log_file.flush()
         });
@@ -370,12 +905,29 @@ impl<F: Format + Send + 'static> LogCsvWriter<F> {
         })
     }
 
-    /// Send a log record to the writer thread / log. Note: be care
-    /// ful to run `finish()` at some point after this, to see the
-    /// reason why that thread failed! (Consider `LogCsvWriter` to be
-    /// a linear type.)
+    /// Send a log record to the writer thread / log, blocking while
+    /// the channel is full (i.e. the thread can't keep up writing to
+    /// disk). Note: be careful to run `finish()` at some point after
+    /// this, to see the reason why that thread failed! (Consider
+    /// `LogCsvWriter` to be a linear type.)
+    #[allow(clippy::result_large_err)] // like `SyncSender::send`
     pub fn send(&self, record: LogCsvRecord) -> Result<(), SendError<LogCsvRecord>> {
-        self.channel_tx.send(record)
+        self.channel_tx
+            .send(WriterMessage::Record(record))
+            .map_err(|SendError(message)| match message {
+                WriterMessage::Record(record) => SendError(record),
+                WriterMessage::Checkpoint => unreachable!(),
+            })
+    }
+
+    /// Have the writer thread flush the records sent so far and sync
+    /// the file to disk, so that a crash later on loses at most the
+    /// records sent after this call. Returns before the sync has
+    /// happened. Errors are handled like for `send()`.
+    pub fn checkpoint(&self) -> Result<(), SendError<()>> {
+        self.channel_tx
+            .send(WriterMessage::Checkpoint)
+            .map_err(|_| SendError(()))
     }
 
     /// Finish writing and flushing all buffered messages. Should
@@ -395,3 +947,178 @@ impl<F: Format + Send + 'static> LogCsvWriter<F> {
         }
     }
 }
+
+/// A log file for the tests, at a path in the temporary directory
+/// unique to the test `name`; removed when dropped, also when an
+/// assertion fails.
+#[cfg(test)]
+struct TestLogFile {
+    path: Arc<Path>,
+}
+
+#[cfg(test)]
+impl TestLogFile {
+    /// Just the path, with the given extension (e.g. `csv.gz`); the
+    /// file is created by the test.
+    fn new(name: &str, extension: &str) -> Self {
+        let path = std::env::temp_dir()
+            .join(format!(
+                "api-query-{name}-{}.{extension}",
+                std::process::id()
+            ))
+            .into();
+        Self { path }
+    }
+
+    /// A `.csv` file with the given contents.
+    fn with_contents(name: &str, contents: &str) -> Result<Self> {
+        let file = Self::new(name, "csv");
+        std::fs::write(&file.path, contents)?;
+        Ok(file)
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestLogFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[test]
+fn t_read_gzipped_log() -> Result<()> {
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+
+    let log = "line in query file,repetition,start,end,d,Ok/Err,status,length,crc,error\n\
+               1,0,1.5,2.5,1,Ok,200 OK,5,crc:123,\n\
+               2,0,1.5,2.5,1,Err,,,,\"multi\nline\"\n";
+    let file = TestLogFile::with_contents("t_read_gzipped_log", log)?;
+    let gz_file = TestLogFile::new("t_read_gzipped_log", "csv.gz");
+    let mut encoder = GzEncoder::new(File::create(&gz_file.path)?, Compression::default());
+    encoder.write_all(log.as_bytes())?;
+    encoder.finish()?;
+
+    let read = |path: &Arc<Path>| -> Result<Vec<String>> {
+        open_log_reader(path.clone())?
+            .map(|record| Ok(format!("{:?}", record?)))
+            .collect()
+    };
+    let records = read(&file.path)?;
+    assert_eq!(records.len(), 2);
+    assert_eq!(records, read(&gz_file.path)?);
+    Ok(())
+}
+
+#[test]
+fn t_parallel_log_reader() -> Result<()> {
+    // Multi-line quoted fields in various places, so that with small
+    // chunk sizes, some of them straddle chunk boundaries; and a
+    // malformed row (invalid line number)
+    let log = "line in query file,repetition,start,end,d,Ok/Err,status,length,crc,error\n\
+               1,0,1.5,2.5,1,Ok,200 OK,5,crc:123,\n\
+               2,0,1.5,2.5,1,Err,,,,\"multi\nline\nerror, with comma\"\n\
+               3,0,1.5,2.5,1,Ok,404 Not Found,7,crc:456,\n\
+               x,0,1.5,2.5,1,Ok,200 OK,5,crc:123,\n\
+               4,1,1.5,2.5,1,Err,,,,\"\nstarts with newline\"\n\
+               5,0,1.5,2.5,1,Ok,200 OK,9,crc:789,\n";
+    let file = TestLogFile::with_contents("t_parallel_log_reader", log)?;
+    let path = &file.path;
+
+    let summarize = |records: Vec<Result<LogCsvRecord>>| -> Vec<String> {
+        records
+            .into_iter()
+            .map(|record| match record {
+                Ok(record) => format!("{record:?}"),
+                Err(e) => format!("error: {e:#}"),
+            })
+            .collect()
+    };
+    let serial = summarize(LogCsvReader::open(path.clone())?.collect());
+    let mut results = Vec::new();
+    for chunk_size in [1, 10, 50, 100, 1 << 20] {
+        for num_threads in [1, 3] {
+            results.push((
+                chunk_size,
+                num_threads,
+                ParallelLogCsvReader::open(path.clone(), num_threads, chunk_size)
+                    .map(|reader| summarize(reader.collect())),
+            ));
+        }
+    }
+
+    assert_eq!(serial.len(), 6);
+    assert!(serial[3].starts_with(&format!("error: bad row at {path:?}:7: ")));
+    assert_eq!(
+        serial
+            .iter()
+            .filter(|record| record.starts_with("error"))
+            .count(),
+        1
+    );
+    assert!(serial[1].contains("multi\\nline\\nerror, with comma"));
+    for (chunk_size, num_threads, records) in results {
+        assert_eq!(
+            records?, serial,
+            "chunk_size {chunk_size}, num_threads {num_threads}"
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn t_attempts_round_trip() -> Result<()> {
+    use std::time::UNIX_EPOCH;
+
+    let file = TestLogFile::new("t_attempts_round_trip", "csv");
+    let path = &file.path;
+    let time = |secs| UnixTimeWrap(UNIX_EPOCH + Duration::from_secs(secs));
+    let record = |line, attempts| {
+        LogCsvRecord(
+            QueryReference { query_index: line },
+            0,
+            time(1),
+            time(2),
+            1.,
+            LogCsvResult::Ok(LogCsvOk {
+                status: StatusCode::OK,
+                length: 5,
+                crc: Crc(123),
+                content_type: None,
+                ttfb: None,
+                request_crc: None,
+                attempts,
+                ndjson_lines: None,
+            }),
+        )
+    };
+    let writer = LogCsvWriter::create(
+        path.clone(),
+        true,
+        LogCsvNormalFormat,
+        None,
+        DEFAULT_CHANNEL_CAPACITY,
+    )?;
+    writer.send(record(0, Some(3)))?;
+    writer.send(record(1, None))?;
+    writer.finish()?;
+    let records: Vec<LogCsvRecord> = LogCsvReader::open(path.clone())?.collect::<Result<_>>()?;
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].attempts(), Some(3));
+    assert_eq!(records[1].attempts(), None);
+    assert_eq!(
+        records[0].status_length_crc(),
+        Some((StatusCode::OK, 5, Crc(123)))
+    );
+
+    // Logs from before the attempts column still read fine
+    let old_log = "line in query file,repetition,start,end,d,Ok/Err,status,length,crc,error,\
+                   content type,ttfb,request crc\n\
+                   1,0,1.5,2.5,1,Ok,200 OK,5,crc:123,,,,\n";
+    let old_file = TestLogFile::with_contents("t_attempts_round_trip-old", old_log)?;
+    let records: Vec<LogCsvRecord> =
+        LogCsvReader::open(old_file.path.clone())?.collect::<Result<_>>()?;
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].attempts(), None);
+    Ok(())
+}
@@ -0,0 +1,225 @@
+//! Per-query aggregation of the results in an api-query CSV log, as
+//! used by `api-query-log compare`.
+
+use std::{path::Path, sync::Arc, time::SystemTime};
+
+use reqwest::StatusCode;
+
+use crate::{
+    auto_vec::AutoVec,
+    log_csv::LogCsvRecord,
+    my_crc::Crc,
+    types::{QueryReference, QueryReferenceWithRepetition},
+};
+
+/// A repetition of a query that gave a different result than the first
+/// time the query was run.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SumError {
+    NonMatchingCrc {
+        reference: QueryReferenceWithRepetition,
+        crc: (StatusCode, usize, Crc),
+    },
+}
+
+/// Which parts of two results have to be equal for them to count as
+/// the same
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareKey {
+    StatusLengthCrc,
+    /// `--ignore-status`
+    LengthCrc,
+    /// `--crc-only`, e.g. for old logs with unreliable lengths
+    Crc,
+}
+
+/// Status, length and CRC of the first successful run of each query
+/// (line) in a log file, and the repetitions that differ from it.
+pub struct Sums {
+    path: Arc<Path>,
+    sums: AutoVec<(StatusCode, usize, Crc)>,
+    /// The request CRC of the first successful run, if logged
+    request_crcs: AutoVec<Option<Crc>>,
+    /// How many times the request of the first successful run was
+    /// sent, if logged
+    attempts: AutoVec<Option<u32>>,
+    seen: AutoVec<u8>,
+    errors: Vec<SumError>,
+    successes: usize,
+    compare_key: CompareKey,
+    /// The earliest and latest start time of all added records
+    /// (including errors)
+    start_range: Option<(SystemTime, SystemTime)>,
+}
+
+impl Sums {
+    /// `path` is the log file the records come from (just for
+    /// reporting). Repetitions need to match the first run in the
+    /// parts given by `compare_key`.
+    pub fn new(path: Arc<Path>, compare_key: CompareKey) -> Self {
+        Self {
+            path,
+            sums: AutoVec::new((StatusCode::from_u16(200).unwrap(), 13131313131313, Crc(0))),
+            request_crcs: AutoVec::new(None),
+            attempts: AutoVec::new(None),
+            seen: AutoVec::new(0),
+            errors: Default::default(),
+            successes: Default::default(),
+            compare_key,
+            start_range: None,
+        }
+    }
+
+    /// Whether two results count as the same, as per `compare_key`.
+    pub fn same(&self, a: (StatusCode, usize, Crc), b: (StatusCode, usize, Crc)) -> bool {
+        match self.compare_key {
+            CompareKey::StatusLengthCrc => a == b,
+            CompareKey::LengthCrc => (a.1, a.2) == (b.1, b.2),
+            CompareKey::Crc => a.2 == b.2,
+        }
+    }
+
+    pub fn path(&self) -> &Arc<Path> {
+        &self.path
+    }
+
+    /// One more than the highest query index seen.
+    pub fn len(&self) -> usize {
+        assert_eq!(self.sums.len(), self.seen.len());
+        self.sums.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Add a record; records for hard errors are ignored.
+    pub fn add(&mut self, record: &LogCsvRecord) {
+        let start = *record.2;
+        self.start_range = Some(match self.start_range {
+            Some((first, last)) => (first.min(start), last.max(start)),
+            None => (start, start),
+        });
+        if let Some(crc) = record.status_length_crc() {
+            let i = record.query_reference().query_index_usize();
+            let now_uses = self.seen.saturating_inc(i);
+            if now_uses > 1 {
+                let first_crc = self.sums.get_copy(i);
+                if self.same(crc, first_crc) {
+                    self.successes += 1;
+                } else {
+                    self.errors.push(SumError::NonMatchingCrc {
+                        reference: record.query_reference_with_repetition(),
+                        crc,
+                    });
+                }
+            } else {
+                self.sums.set(i, crc);
+                self.request_crcs.set(i, record.request_crc());
+                self.attempts.set(i, record.attempts());
+            }
+        } else {
+            // ignore errors
+        }
+    }
+
+    /// The earliest and latest start time of the added records, if
+    /// any.
+    pub fn start_range(&self) -> Option<(SystemTime, SystemTime)> {
+        self.start_range
+    }
+
+    /// How many successful runs of the query were added (saturating
+    /// at 255).
+    pub fn num_seen(&self, reference: QueryReference) -> u8 {
+        self.seen.get_copy(reference.query_index_usize())
+    }
+
+    /// Status, length and CRC of the first successful run of the
+    /// query, if any.
+    pub fn first(&self, reference: QueryReference) -> Option<(StatusCode, usize, Crc)> {
+        if self.num_seen(reference) > 0 {
+            Some(self.sums.get_copy(reference.query_index_usize()))
+        } else {
+            None
+        }
+    }
+
+    /// The CRC of the request body of the first successful run of
+    /// the query, if it was logged (`--crc-request`).
+    pub fn first_request_crc(&self, reference: QueryReference) -> Option<Crc> {
+        *self.request_crcs.get(reference.query_index_usize())
+    }
+
+    /// How many times the request of the first successful run of the
+    /// query was sent, if it was logged.
+    pub fn first_attempts(&self, reference: QueryReference) -> Option<u32> {
+        *self.attempts.get(reference.query_index_usize())
+    }
+
+    /// The number of repetitions that matched the first run.
+    pub fn successes(&self) -> usize {
+        self.successes
+    }
+
+    /// The repetitions that did not match the first run, in the order
+    /// they were added (see `sort_errors`).
+    pub fn errors(&self) -> &[SumError] {
+        &self.errors
+    }
+
+    pub fn sort_errors(&mut self) {
+        self.errors.sort();
+    }
+}
+
+/// Tracks which (line, repetition) pairs have been seen in a log, to
+/// detect duplicate entries (which would skew the sums).
+pub struct DuplicateCheck {
+    /// Per query index, a bitset of the repetitions seen
+    seen: AutoVec<Vec<u64>>,
+}
+
+impl DuplicateCheck {
+    pub fn new() -> Self {
+        Self {
+            seen: AutoVec::new(Vec::new()),
+        }
+    }
+
+    /// Mark `reference` as seen; returns true if it was seen before.
+    pub fn check(&mut self, reference: QueryReferenceWithRepetition) -> bool {
+        let bits = self
+            .seen
+            .get_mut(reference.query_reference.query_index_usize());
+        let repetition = reference.repetition as usize;
+        let (word, bit) = (repetition / 64, 1u64 << (repetition % 64));
+        if bits.len() <= word {
+            bits.resize(word + 1, 0);
+        }
+        let was_seen = bits[word] & bit != 0;
+        bits[word] |= bit;
+        was_seen
+    }
+}
+
+impl Default for DuplicateCheck {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test]
+fn t_duplicate_check() {
+    let mut check = DuplicateCheck::new();
+    let reference = |query_index, repetition| QueryReferenceWithRepetition {
+        query_reference: QueryReference { query_index },
+        repetition,
+    };
+    assert!(!check.check(reference(3, 0)));
+    assert!(!check.check(reference(3, 100)));
+    assert!(!check.check(reference(0, 100)));
+    assert!(check.check(reference(3, 100)));
+    assert!(check.check(reference(3, 0)));
+    assert!(!check.check(reference(3, 1)));
+}
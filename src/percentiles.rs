@@ -0,0 +1,227 @@
+//! Quantiles over `f64` samples, shared by everything that reports
+//! percentiles.
+
+use std::collections::BTreeMap;
+
+/// How the samples are kept
+#[derive(Debug, Clone)]
+enum Samples {
+    /// All samples (8 bytes each)
+    Exact { values: Vec<f64>, sorted: bool },
+    /// Counts per logarithmic bucket (like DDSketch): a positive
+    /// value `v` goes into bucket `ceil(log_gamma(v))`; zero (and
+    /// negative values) into bucket `i64::MIN`.
+    Bucketed {
+        gamma: f64,
+        buckets: BTreeMap<i64, u64>,
+    },
+}
+
+/// Collects samples and computes quantiles, either exactly (keeping
+/// all samples) or approximately with a bounded relative error, in
+/// memory proportional to the range of magnitudes of the samples.
+#[derive(Debug, Clone)]
+pub struct Percentiles {
+    samples: Samples,
+    count: u64,
+    sum: f64,
+    sum_squares: f64,
+    min: f64,
+    max: f64,
+}
+
+impl Percentiles {
+    fn with_samples(samples: Samples) -> Self {
+        Self {
+            samples,
+            count: 0,
+            sum: 0.,
+            sum_squares: 0.,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+
+    pub fn exact() -> Self {
+        Self::with_samples(Samples::Exact {
+            values: Vec::new(),
+            sorted: true,
+        })
+    }
+
+    /// Quantiles are off by at most `relative_error` (e.g. 0.01 for
+    /// 1%, must be in `(0, 1)`); negative samples count as 0 for
+    /// them.
+    pub fn bucketed(relative_error: f64) -> Self {
+        assert!(relative_error > 0. && relative_error < 1.);
+        Self::with_samples(Samples::Bucketed {
+            gamma: (1. + relative_error) / (1. - relative_error),
+            buckets: BTreeMap::new(),
+        })
+    }
+
+    /// NaN samples are ignored.
+    pub fn add(&mut self, value: f64) {
+        if value.is_nan() {
+            return;
+        }
+        self.count += 1;
+        self.sum += value;
+        self.sum_squares += value * value;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        match &mut self.samples {
+            Samples::Exact { values, sorted } => {
+                values.push(value);
+                *sorted = false;
+            }
+            Samples::Bucketed { gamma, buckets } => {
+                *buckets.entry(bucket_key(*gamma, value)).or_insert(0) += 1;
+            }
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Zero if empty (also for the other statistics)
+    pub fn min(&self) -> f64 {
+        if self.is_empty() {
+            0.
+        } else {
+            self.min
+        }
+    }
+
+    pub fn max(&self) -> f64 {
+        if self.is_empty() {
+            0.
+        } else {
+            self.max
+        }
+    }
+
+    pub fn mean(&self) -> f64 {
+        if self.is_empty() {
+            0.
+        } else {
+            self.sum / self.count as f64
+        }
+    }
+
+    /// Population standard deviation
+    pub fn stdev(&self) -> f64 {
+        if self.is_empty() {
+            return 0.;
+        }
+        let mean = self.mean();
+        (self.sum_squares / self.count as f64 - mean * mean)
+            .max(0.)
+            .sqrt()
+    }
+
+    /// The value that a fraction `q` (0..=1) of the samples is at
+    /// most (nearest-rank method). Sorts the samples if needed.
+    pub fn quantile(&mut self, q: f64) -> f64 {
+        if self.is_empty() {
+            return 0.;
+        }
+        let rank = ((q.clamp(0., 1.) * self.count as f64).ceil() as u64).max(1);
+        match &mut self.samples {
+            Samples::Exact { values, sorted } => {
+                if !*sorted {
+                    values.sort_unstable_by(f64::total_cmp);
+                    *sorted = true;
+                }
+                values[(rank - 1) as usize]
+            }
+            Samples::Bucketed { gamma, buckets } => {
+                let mut seen = 0;
+                for (&key, &count) in buckets.iter() {
+                    seen += count;
+                    if seen >= rank {
+                        return bucket_value(*gamma, key).clamp(self.min, self.max);
+                    }
+                }
+                self.max
+            }
+        }
+    }
+
+    /// The fraction (0..=1) of the samples that lie within
+    /// `low..=high` (for bucketed samples, judged by their bucket's
+    /// value).
+    pub fn fraction_within(&self, low: f64, high: f64) -> f64 {
+        if self.is_empty() {
+            return 0.;
+        }
+        let n: u64 = match &self.samples {
+            Samples::Exact { values, .. } => {
+                values.iter().filter(|&&v| low <= v && v <= high).count() as u64
+            }
+            Samples::Bucketed { gamma, buckets } => buckets
+                .iter()
+                .filter(|(&key, _)| {
+                    let v = bucket_value(*gamma, key);
+                    low <= v && v <= high
+                })
+                .map(|(_, &count)| count)
+                .sum(),
+        };
+        n as f64 / self.count as f64
+    }
+}
+
+fn bucket_key(gamma: f64, value: f64) -> i64 {
+    if value > 0. {
+        (value.ln() / gamma.ln()).ceil() as i64
+    } else {
+        i64::MIN
+    }
+}
+
+/// The value representing the bucket, which is within the relative
+/// error of all values in it.
+fn bucket_value(gamma: f64, key: i64) -> f64 {
+    if key == i64::MIN {
+        0.
+    } else {
+        2. * gamma.powf(key as f64) / (gamma + 1.)
+    }
+}
+
+#[test]
+fn t_percentiles() {
+    let mut exact = Percentiles::exact();
+    let mut bucketed = Percentiles::bucketed(0.01);
+    assert_eq!(exact.quantile(0.5), 0.);
+    for i in (1..=1000).rev() {
+        exact.add(f64::from(i));
+        bucketed.add(f64::from(i));
+    }
+    exact.add(f64::NAN);
+    assert_eq!(exact.len(), 1000);
+    assert_eq!(exact.mean(), 500.5);
+    assert_eq!(bucketed.mean(), 500.5);
+    assert_eq!(exact.quantile(0.), 1.);
+    assert_eq!(exact.quantile(0.5), 500.);
+    assert_eq!(exact.quantile(0.99), 990.);
+    assert_eq!(exact.quantile(1.), 1000.);
+    for q in [0., 0.25, 0.5, 0.9, 0.99, 1.] {
+        let (e, b) = (exact.quantile(q), bucketed.quantile(q));
+        assert!(
+            (b - e).abs() <= e * 0.01,
+            "q {}: exact {}, bucketed {}",
+            q,
+            e,
+            b
+        );
+    }
+    assert_eq!(exact.fraction_within(1., 100.), 0.1);
+    assert!((bucketed.fraction_within(1., 100.) - 0.1).abs() < 0.005);
+}
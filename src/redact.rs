@@ -0,0 +1,83 @@
+//! Masking of secrets (currently the credentials from the endpoint
+//! URL, see `BasicAuth`) in error messages and `--dry-run` output, so
+//! that they don't end up in retained CI logs.
+
+use std::borrow::Cow;
+
+use anyhow::anyhow;
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use crate::run_query::BasicAuth;
+
+/// What secrets are replaced with
+const MASK: &str = "***";
+
+#[derive(Debug, Default)]
+pub struct Redactor {
+    secrets: Vec<String>,
+}
+
+impl Redactor {
+    /// A redactor that leaves everything unchanged (`--no-redact`, or
+    /// no secrets configured)
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    /// Mask the password, and the `Authorization` header value built
+    /// from the credentials, of `basic_auth`.
+    pub fn for_basic_auth(basic_auth: &BasicAuth) -> Self {
+        let BasicAuth { username, password } = basic_auth;
+        let password = password.as_deref().unwrap_or("");
+        let mut secrets = vec![STANDARD.encode(format!("{username}:{password}"))];
+        if !password.is_empty() {
+            secrets.push(password.into());
+        }
+        Self { secrets }
+    }
+
+    /// Like `for_basic_auth`, for all of the given credentials (e.g.
+    /// from the URLs of `--targets-file`).
+    pub fn for_basic_auths<'b>(basic_auths: impl IntoIterator<Item = &'b BasicAuth>) -> Self {
+        let secrets = basic_auths
+            .into_iter()
+            .flat_map(|basic_auth| Self::for_basic_auth(basic_auth).secrets)
+            .collect();
+        Self { secrets }
+    }
+
+    pub fn redact<'s>(&self, string: &'s str) -> Cow<'s, str> {
+        let mut string = Cow::Borrowed(string);
+        for secret in &self.secrets {
+            if string.contains(secret.as_str()) {
+                string = string.replace(secret.as_str(), MASK).into();
+            }
+        }
+        string
+    }
+
+    /// The error as it is shown by `{:?}`, with secrets masked. This
+    /// loses the error types in the chain, thus only use it for
+    /// errors that are not inspected anymore.
+    pub fn redact_error(&self, error: anyhow::Error) -> anyhow::Error {
+        if self.secrets.is_empty() {
+            error
+        } else {
+            anyhow!("{}", self.redact(&format!("{error:?}")))
+        }
+    }
+}
+
+#[test]
+fn t_redact() {
+    let redactor = Redactor::for_basic_auth(&BasicAuth {
+        username: "user".into(),
+        password: Some("pass".into()),
+    });
+    assert_eq!(
+        redactor.redact("authorization: Basic dXNlcjpwYXNz, {\"pw\":\"pass\"}"),
+        "authorization: Basic ***, {\"pw\":\"***\"}"
+    );
+    assert!(matches!(redactor.redact("nothing"), Cow::Borrowed(_)));
+    assert_eq!(Redactor::none().redact("pass"), "pass");
+}
@@ -0,0 +1,83 @@
+//! Validation of responses against a JSON Schema
+//! (`--validate-schema`).
+
+use std::{fmt, path::Path};
+
+use anyhow::{anyhow, Context, Result};
+use jsonschema::Validator;
+use serde_json::Value;
+
+/// How many validation errors are included in the error message for
+/// a response
+const MAX_REPORTED_ERRORS: usize = 3;
+
+/// Context for the errors from `ResponseSchema::validate`, so that
+/// they can be tallied separately from other errors (check with
+/// `error.is::<SchemaMismatch>()`).
+#[derive(Debug)]
+pub struct SchemaMismatch;
+
+impl fmt::Display for SchemaMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("response does not match the JSON schema")
+    }
+}
+
+pub struct ResponseSchema {
+    validator: Validator,
+}
+
+impl ResponseSchema {
+    pub fn from_value(schema: &Value) -> Result<Self> {
+        let validator =
+            jsonschema::validator_for(schema).map_err(|e| anyhow!("invalid JSON schema: {e}"))?;
+        Ok(Self { validator })
+    }
+
+    /// Read the schema from the JSON file at `path`.
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let schema = std::fs::read(path).with_context(|| anyhow!("reading schema {path:?}"))?;
+        let schema: Value = serde_json::from_slice(&schema)
+            .with_context(|| anyhow!("parsing schema {path:?} as JSON"))?;
+        Self::from_value(&schema).with_context(|| anyhow!("in schema {path:?}"))
+    }
+
+    /// Check the response `body`; an error (with `SchemaMismatch`
+    /// context) if it is not JSON or doesn't match the schema.
+    pub fn validate(&self, body: &[u8]) -> Result<()> {
+        let value: Value = serde_json::from_slice(body)
+            .map_err(|e| anyhow!("not valid JSON: {e}"))
+            .context(SchemaMismatch)?;
+        let mut messages: Vec<String> = Vec::new();
+        let mut num_errors = 0;
+        for error in self.validator.iter_errors(&value) {
+            num_errors += 1;
+            if messages.len() < MAX_REPORTED_ERRORS {
+                messages.push(format!("{error} (at {:?})", error.instance_path.as_str()));
+            }
+        }
+        if num_errors == 0 {
+            return Ok(());
+        }
+        let mut message = messages.join("; ");
+        if num_errors > messages.len() {
+            message.push_str(&format!("; and {} more", num_errors - messages.len()));
+        }
+        Err(anyhow!("{message}").context(SchemaMismatch))
+    }
+}
+
+#[test]
+fn t_validate() -> Result<()> {
+    let schema = ResponseSchema::from_value(&serde_json::json!({
+        "type": "object",
+        "properties": { "data": { "type": "array", "items": { "type": "integer" } } },
+        "required": ["data"]
+    }))?;
+    schema.validate(br#"{"data": [1, 2]}"#)?;
+    let e = schema.validate(br#"{"data": [1, "2"]}"#).unwrap_err();
+    assert!(e.is::<SchemaMismatch>());
+    assert!(format!("{e:#}").contains("/data/1"));
+    assert!(schema.validate(b"{").unwrap_err().is::<SchemaMismatch>());
+    Ok(())
+}
@@ -0,0 +1,463 @@
+//! Human-readable end-of-run summaries
+
+use std::{collections::BTreeMap, convert::TryInto, fmt::Write, time::Duration};
+
+use reqwest::StatusCode;
+
+use crate::{
+    auto_vec::AutoVec,
+    error_kind::ErrorKind,
+    latency::Latencies,
+    percentiles::Percentiles,
+    types::{Queries, QueryReference},
+};
+
+const GREEN: &str = "\x1b[32m";
+const RED: &str = "\x1b[31m";
+const RESET: &str = "\x1b[0m";
+
+fn status_color(status: StatusCode) -> Option<&'static str> {
+    if status.is_success() {
+        Some(GREEN)
+    } else if status.is_server_error() {
+        Some(RED)
+    } else {
+        None
+    }
+}
+
+/// Format the status tally as an aligned table with status, count
+/// and percentage columns, followed by a bar if `width` (the terminal
+/// width) leaves room for it. If `color` is true, the rows are
+/// colored via ANSI escape sequences.
+pub fn status_table(
+    status_tally: &BTreeMap<StatusCode, usize>,
+    color: bool,
+    width: usize,
+) -> String {
+    let total: usize = status_tally.values().sum();
+    let rows: Vec<(StatusCode, usize, String, String, String)> = status_tally
+        .iter()
+        .map(|(status, count)| {
+            let percent = if total == 0 {
+                0.
+            } else {
+                *count as f64 * 100. / total as f64
+            };
+            (
+                *status,
+                *count,
+                status.to_string(),
+                count.to_string(),
+                format!("{percent:.1}%"),
+            )
+        })
+        .collect();
+
+    let status_width = rows
+        .iter()
+        .map(|(_, _, s, _, _)| s.len())
+        .chain(["status".len()])
+        .max()
+        .expect("at least the header");
+    let count_width = rows
+        .iter()
+        .map(|(_, _, _, c, _)| c.len())
+        .chain(["count".len()])
+        .max()
+        .expect("at least the header");
+    let percent_width = "100.0%".len().max("percent".len());
+    let bar_width = width
+        .saturating_sub(status_width + count_width + percent_width + 6)
+        .min(50);
+
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "{:<status_width$}  {:>count_width$}  {:>percent_width$}",
+        "status", "count", "percent"
+    );
+    for (status, count, status_str, count_str, percent_str) in rows {
+        let color_start = if color { status_color(status) } else { None };
+        if let Some(c) = color_start {
+            out.push_str(c);
+        }
+        let _ = write!(
+            out,
+            "{status_str:<status_width$}  {count_str:>count_width$}  {percent_str:>percent_width$}"
+        );
+        if bar_width > 0 && total > 0 {
+            let n = (count * bar_width + total / 2) / total;
+            out.push_str("  ");
+            out.push_str(&"#".repeat(n));
+        }
+        if color_start.is_some() {
+            out.push_str(RESET);
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Format the distribution of the response sizes in `lengths` as a
+/// table with one row per power-of-two range of bytes (from the
+/// smallest to the largest size seen), with count and percentage
+/// columns and a bar like `status_table`, e.g. to reveal bimodal
+/// sizes that averages hide.
+pub fn size_histogram(lengths: &Percentiles, width: usize) -> String {
+    let total = lengths.len();
+    let mut rows: Vec<(String, u64)> = Vec::new();
+    if total > 0 {
+        let (min, max) = (lengths.min() as u64, lengths.max() as u64);
+        let (mut low, mut high) = (0_u64, 0_u64);
+        loop {
+            if high >= min {
+                let count = (lengths.fraction_within(low as f64, high as f64) * total as f64)
+                    .round() as u64;
+                let range = if low == high {
+                    low.to_string()
+                } else {
+                    format!("{low}-{high}")
+                };
+                rows.push((range, count));
+            }
+            if high >= max {
+                break;
+            }
+            low = high + 1;
+            high = low.saturating_mul(2) - 1;
+        }
+    }
+
+    let range_width = rows
+        .iter()
+        .map(|(r, _)| r.len())
+        .chain(["bytes".len()])
+        .max()
+        .expect("at least the header");
+    let count_width = rows
+        .iter()
+        .map(|(_, c)| c.to_string().len())
+        .chain(["count".len()])
+        .max()
+        .expect("at least the header");
+    let percent_width = "100.0%".len().max("percent".len());
+    let bar_width = width
+        .saturating_sub(range_width + count_width + percent_width + 6)
+        .min(50) as u64;
+
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "{:>range_width$}  {:>count_width$}  {:>percent_width$}",
+        "bytes", "count", "percent"
+    );
+    for (range, count) in rows {
+        let percent = format!("{:.1}%", count as f64 * 100. / total as f64);
+        let _ = write!(
+            out,
+            "{range:>range_width$}  {count:>count_width$}  {percent:>percent_width$}"
+        );
+        if bar_width > 0 {
+            let n = (count * bar_width + total / 2) / total;
+            out.push_str("  ");
+            out.push_str(&"#".repeat(n as usize));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Response counts for one query: hard errors, then status classes
+/// 1xx to 5xx.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QueryStatusCounts([u32; 6]);
+
+impl QueryStatusCounts {
+    pub fn total(&self) -> u32 {
+        self.0.iter().sum()
+    }
+
+    pub fn hard_errors(&self) -> u32 {
+        self.0[0]
+    }
+
+    /// Count for the status class (1 to 5)
+    pub fn class(&self, class: usize) -> u32 {
+        self.0[class]
+    }
+
+    /// Hard errors, 4xx and 5xx responses
+    pub fn failures(&self) -> u32 {
+        self.0[0] + self.0[4] + self.0[5]
+    }
+
+    pub fn failure_rate(&self) -> f64 {
+        let total = self.total();
+        if total == 0 {
+            0.
+        } else {
+            f64::from(self.failures()) / f64::from(total)
+        }
+    }
+}
+
+/// Status counts per query, for finding out which queries fail.
+pub struct PerQueryStats {
+    counts: AutoVec<QueryStatusCounts>,
+}
+
+impl PerQueryStats {
+    pub fn new() -> Self {
+        Self {
+            counts: AutoVec::new(Default::default()),
+        }
+    }
+}
+
+impl Default for PerQueryStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PerQueryStats {
+    /// Record a response with the given status, or a hard error if
+    /// `None`.
+    pub fn add(&mut self, query_reference: QueryReference, status: Option<StatusCode>) {
+        let i = match status {
+            Some(status) => usize::from(status.as_u16() / 100).clamp(1, 5),
+            None => 0,
+        };
+        let counts = self.counts.get_mut(query_reference.query_index_usize());
+        counts.0[i] = counts.0[i].saturating_add(1);
+    }
+
+    /// Format the (up to) `n` queries with the highest failure rates
+    /// as a table; queries without failures are not shown.
+    pub fn top_offenders(&self, n: usize, queries: &Queries) -> String {
+        let mut offenders: Vec<(QueryReference, QueryStatusCounts)> = (0..self.counts.len())
+            .map(|i| {
+                (
+                    QueryReference {
+                        query_index: i.try_into().expect("was created from u32"),
+                    },
+                    self.counts.get_copy(i),
+                )
+            })
+            .filter(|(_, counts)| counts.failures() > 0)
+            .collect();
+        offenders.sort_by(|(a_ref, a), (b_ref, b)| {
+            b.failure_rate()
+                .total_cmp(&a.failure_rate())
+                .then(b.failures().cmp(&a.failures()))
+                .then(a_ref.cmp(b_ref))
+        });
+
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "line\trequests\t1xx\t2xx\t3xx\t4xx\t5xx\terrors\tfailure rate\tquery string"
+        );
+        for (query_reference, counts) in offenders.into_iter().take(n) {
+            let query_string = queries
+                .borrow_queries()
+                .get(query_reference.query_index_usize())
+                .map(|query| query.string)
+                .unwrap_or("<error: line is not in the queries>");
+            let _ = writeln!(
+                out,
+                "{query_reference}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{:.1}%\t{query_string}",
+                counts.total(),
+                counts.class(1),
+                counts.class(2),
+                counts.class(3),
+                counts.class(4),
+                counts.class(5),
+                counts.hard_errors(),
+                counts.failure_rate() * 100.,
+            );
+        }
+        out
+    }
+}
+
+/// Format `n` with 2 decimals and the largest of `units` (each
+/// `scale` times the previous one, starting with `base`) that keeps
+/// it at least 1, like wrk does.
+fn wrk_units(mut n: f64, scale: f64, base: &str, units: &[&'static str]) -> String {
+    let mut unit = base;
+    for u in units {
+        if n < scale {
+            break;
+        }
+        n /= scale;
+        unit = u;
+    }
+    format!("{n:.2}{unit}")
+}
+
+fn wrk_time(duration: Duration) -> String {
+    let us = duration.as_secs_f64() * 1e6;
+    if us >= 1e6 {
+        wrk_units(us / 1e6, 60., "s", &["m", "h"])
+    } else {
+        wrk_units(us, 1000., "us", &["ms", "s"])
+    }
+}
+
+fn wrk_binary(n: f64) -> String {
+    wrk_units(n, 1024., "", &["K", "M", "G", "T", "P"])
+}
+
+/// Right-align `s` in `width` columns, where units of less than 2
+/// letters are padded to keep the numbers aligned (wrk's
+/// `print_units`).
+fn wrk_column(out: &mut String, s: &str, width: usize) {
+    let letters = s
+        .bytes()
+        .rev()
+        .take(2)
+        .take_while(|b| b.is_ascii_alphabetic())
+        .count();
+    let pad = 2 - letters;
+    let _ = write!(out, "{s:>w$}{}", &"  "[..pad], w = width - pad);
+}
+
+/// The figures shown by `wrk_report`
+pub struct WrkFigures<'a> {
+    pub url: &'a str,
+    pub concurrency: usize,
+    /// The duration of the whole run
+    pub elapsed: Duration,
+    /// Of the requests that got a response
+    pub latencies: &'a mut Latencies,
+    /// Total size of the responses
+    pub bytes: usize,
+    /// Responses with a status of 400 or higher
+    pub num_error_statuses: usize,
+    pub error_kind_tally: &'a BTreeMap<ErrorKind, usize>,
+}
+
+/// Format a report in the style of the one of the wrk benchmarking
+/// tool (with `--latency`), for tools that parse that. There are no
+/// threads in the wrk sense, thus the `Req/Sec` thread statistics are
+/// left out; hard errors are shown as wrk's socket errors.
+pub fn wrk_report(figures: WrkFigures) -> String {
+    let WrkFigures {
+        url,
+        concurrency,
+        elapsed,
+        latencies,
+        bytes,
+        num_error_statuses,
+        error_kind_tally,
+    } = figures;
+    let secs = elapsed.as_secs_f64().max(1e-9);
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "Running {}s test @ {url}",
+        elapsed.as_secs_f64().round()
+    );
+    let _ = writeln!(out, "  1 threads and {concurrency} connections");
+    let _ = writeln!(
+        out,
+        "  Thread Stats{:>6}{:>11}{:>8}{:>12}",
+        "Avg", "Stdev", "Max", "+/- Stdev"
+    );
+    let (mean, stdev) = (latencies.mean(), latencies.stdev());
+    out.push_str("    Latency   ");
+    wrk_column(&mut out, &wrk_time(mean), 8);
+    wrk_column(&mut out, &wrk_time(stdev), 10);
+    wrk_column(&mut out, &wrk_time(latencies.max()), 9);
+    let _ = writeln!(
+        out,
+        "{:>8.2}%",
+        latencies.fraction_within(mean.saturating_sub(stdev), mean + stdev) * 100.
+    );
+    out.push_str("  Latency Distribution\n");
+    for percent in [50, 75, 90, 99] {
+        let _ = write!(out, "{percent:>7}%");
+        wrk_column(
+            &mut out,
+            &wrk_time(latencies.percentile(f64::from(percent) / 100.)),
+            10,
+        );
+        out.push('\n');
+    }
+    let _ = writeln!(
+        out,
+        "  {} requests in {}, {}B read",
+        latencies.len(),
+        wrk_time(elapsed),
+        wrk_binary(bytes as f64)
+    );
+    let count = |kinds: &[ErrorKind]| -> usize {
+        kinds
+            .iter()
+            .map(|kind| error_kind_tally.get(kind).copied().unwrap_or(0))
+            .sum()
+    };
+    if !error_kind_tally.is_empty() {
+        let _ = writeln!(
+            out,
+            "  Socket errors: connect {}, read {}, write 0, timeout {}",
+            count(&[
+                ErrorKind::Dns,
+                ErrorKind::Connect,
+                ErrorKind::Tls,
+                ErrorKind::ConnectTimeout
+            ]),
+            count(&[
+                ErrorKind::Read,
+                ErrorKind::Decode,
+                ErrorKind::Redirect,
+                ErrorKind::Status,
+                ErrorKind::Other
+            ]),
+            count(&[ErrorKind::Timeout]),
+        );
+    }
+    if num_error_statuses > 0 {
+        let _ = writeln!(out, "  Non-2xx or 3xx responses: {num_error_statuses}");
+    }
+    let _ = writeln!(out, "Requests/sec: {:>9.2}", latencies.len() as f64 / secs);
+    let _ = writeln!(
+        out,
+        "Transfer/sec: {:>10}B",
+        wrk_binary(bytes as f64 / secs)
+    );
+    out
+}
+
+#[test]
+fn t_size_histogram() {
+    let mut lengths = Percentiles::exact();
+    for length in [3., 3., 5., 6., 7.] {
+        lengths.add(length);
+    }
+    assert_eq!(
+        size_histogram(&lengths, 0),
+        "bytes  count  percent\n  \
+           2-3      2    40.0%\n  \
+           4-7      3    60.0%\n"
+    );
+    assert_eq!(
+        size_histogram(&Percentiles::exact(), 0),
+        "bytes  count  percent\n"
+    );
+}
+
+#[test]
+fn t_wrk_units() {
+    assert_eq!(wrk_time(Duration::from_micros(512)), "512.00us");
+    assert_eq!(wrk_time(Duration::from_micros(2_440)), "2.44ms");
+    assert_eq!(wrk_time(Duration::from_millis(1_500)), "1.50s");
+    assert_eq!(wrk_time(Duration::from_secs(90)), "1.50m");
+    assert_eq!(wrk_binary(13926.4), "13.60K");
+    let mut out = String::new();
+    wrk_column(&mut out, "2.44ms", 8);
+    wrk_column(&mut out, "1.50s", 10);
+    assert_eq!(out, "  2.44ms    1.50s ");
+}
@@ -0,0 +1,268 @@
+//! Substitution of `{{NAME}}` placeholders in queries, with values
+//! given via `--var NAME=VALUE`, and of the built-in variables
+//! `{{LINE}}`, `{{REP}}`, `{{RANDOM_INT:min:max}}` and `{{UUID}}`,
+//! which differ per request.
+
+use std::{borrow::Cow, collections::BTreeMap, str::FromStr};
+
+use anyhow::{anyhow, bail, Context, Result};
+use lazy_static::lazy_static;
+use rand::Rng;
+use regex::{Captures, Regex};
+
+use crate::types::{Queries, QueryReference, QueryReferenceWithRepetition};
+
+/// Variables that are substituted per request, not per query; their
+/// names can't be used with `--var`.
+const BUILTINS: [&str; 4] = ["LINE", "REP", "RANDOM_INT", "UUID"];
+
+/// Built-in variables that make requests non-deterministic
+const RANDOM_BUILTINS: [&str; 2] = ["RANDOM_INT", "UUID"];
+
+lazy_static! {
+    /// Captures the name and the arguments, if any, including the
+    /// leading colon (e.g. ":1:10")
+    static ref PLACEHOLDER: Regex =
+        Regex::new(r"\{\{([A-Za-z_][A-Za-z0-9_]*)((?::[^:{}]*)*)\}\}").expect("valid regex");
+}
+
+/// Whether `name` matches the NAME part of `PLACEHOLDER`
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// The arguments in the second capture group of `PLACEHOLDER`
+fn arguments<'c>(captures: &'c Captures) -> Vec<&'c str> {
+    let args = &captures[2];
+    if args.is_empty() {
+        vec![]
+    } else {
+        args[1..].split(':').collect()
+    }
+}
+
+fn parse_random_int_range(args: &[&str]) -> Result<(i64, i64)> {
+    if let [min, max] = args {
+        let min: i64 = min
+            .parse()
+            .with_context(|| anyhow!("parsing min value {min:?}"))?;
+        let max: i64 = max
+            .parse()
+            .with_context(|| anyhow!("parsing max value {max:?}"))?;
+        if min > max {
+            bail!("min value {min} is larger than max value {max}")
+        }
+        Ok((min, max))
+    } else {
+        bail!("expecting `RANDOM_INT:min:max`")
+    }
+}
+
+/// Check the arguments of a built-in variable
+fn check_builtin(name: &str, args: &[&str]) -> Result<()> {
+    match name {
+        "RANDOM_INT" => {
+            parse_random_int_range(args)?;
+        }
+        _ => {
+            if !args.is_empty() {
+                bail!("variable {name:?} does not take arguments")
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A `NAME=VALUE` pair as given to `--var`
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct VarAssignment {
+    pub name: String,
+    pub value: String,
+}
+
+impl FromStr for VarAssignment {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (name, value) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow!("expecting NAME=VALUE, got {s:?}"))?;
+        if !is_identifier(name) {
+            bail!("invalid variable name {name:?}, must be an identifier")
+        }
+        if BUILTINS.contains(&name) {
+            bail!("variable name {name:?} is reserved for the built-in variable")
+        }
+        Ok(Self {
+            name: name.into(),
+            value: value.into(),
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct TemplateVars {
+    vars: BTreeMap<String, String>,
+}
+
+impl TemplateVars {
+    pub fn new(assignments: Vec<VarAssignment>) -> Result<Self> {
+        let mut vars = BTreeMap::new();
+        for VarAssignment { name, value } in assignments {
+            if vars.contains_key(&name) {
+                bail!("variable {name:?} given multiple times")
+            }
+            vars.insert(name, value);
+        }
+        Ok(Self { vars })
+    }
+
+    /// Replace all placeholders in `string` except for the built-in
+    /// ones; unknown variables are an error.
+    pub fn substitute<'s>(&self, string: &'s str) -> Result<Cow<'s, str>> {
+        let mut error = None;
+        let result = PLACEHOLDER.replace_all(string, |captures: &Captures| {
+            let name = &captures[1];
+            let args = arguments(captures);
+            let check = if BUILTINS.contains(&name) {
+                check_builtin(name, &args).map(|()| captures[0].to_owned())
+            } else if let Some(value) = self.vars.get(name) {
+                if args.is_empty() {
+                    Ok(value.clone())
+                } else {
+                    Err(anyhow!("variable {name:?} does not take arguments"))
+                }
+            } else {
+                Err(anyhow!("unknown template variable {{{{{name}}}}}"))
+            };
+            check.unwrap_or_else(|e| {
+                error.get_or_insert(e);
+                String::new()
+            })
+        });
+        if let Some(e) = error {
+            return Err(e);
+        }
+        Ok(result)
+    }
+
+    /// Substitute the placeholders in all queries, once (independent
+    /// of how often they are going to be run).
+    pub fn substitute_queries(&self, queries: &Queries) -> Result<Queries> {
+        let strings = queries
+            .borrow_queries()
+            .iter()
+            .enumerate()
+            .map(|(i, query)| {
+                self.substitute(query.string).with_context(|| {
+                    let reference = QueryReference {
+                        query_index: i as u32,
+                    };
+                    anyhow!("in query from line {reference}")
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Queries::from_strings(strings)
+    }
+}
+
+/// Whether `string` contains placeholders for built-in variables.
+pub fn has_builtins(string: &str) -> bool {
+    PLACEHOLDER
+        .captures_iter(string)
+        .any(|captures| BUILTINS.contains(&&captures[1]))
+}
+
+/// Whether `string` contains placeholders for random values.
+pub fn has_random_builtins(string: &str) -> bool {
+    PLACEHOLDER
+        .captures_iter(string)
+        .any(|captures| RANDOM_BUILTINS.contains(&&captures[1]))
+}
+
+/// Check the arguments of all built-in variables in `string`.
+pub fn check_builtins(string: &str) -> Result<()> {
+    for captures in PLACEHOLDER.captures_iter(string) {
+        let name = &captures[1];
+        if BUILTINS.contains(&name) {
+            check_builtin(name, &arguments(&captures))
+                .with_context(|| anyhow!("in {:?}", &captures[0]))?;
+        }
+    }
+    Ok(())
+}
+
+/// A random (version 4) UUID
+fn random_uuid(rng: &mut impl Rng) -> String {
+    let mut bytes: [u8; 16] = rng.gen();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|b| format!("{b:02x}")).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+/// Replace the built-in variables in `string`: `{{LINE}}` (1-based
+/// line number in the queries file), `{{REP}}` (0-based repetition),
+/// `{{RANDOM_INT:min:max}}` (a number in the inclusive range) and
+/// `{{UUID}}` (a random UUID), the latter two taken from `rng`.
+pub fn substitute_builtins<'s>(
+    string: &'s str,
+    reference: QueryReferenceWithRepetition,
+    rng: &mut impl Rng,
+) -> Cow<'s, str> {
+    PLACEHOLDER.replace_all(string, |captures: &Captures| match &captures[1] {
+        "LINE" => reference.query_reference.to_string(),
+        "REP" => reference.repetition.to_string(),
+        "RANDOM_INT" => match parse_random_int_range(&arguments(captures)) {
+            Ok((min, max)) => rng.gen_range(min..=max).to_string(),
+            // Was checked by `check_builtins` already
+            Err(_) => captures[0].to_owned(),
+        },
+        "UUID" => random_uuid(rng),
+        _ => captures[0].to_owned(),
+    })
+}
+
+#[test]
+fn t_substitute() -> Result<()> {
+    let vars = TemplateVars::new(vec!["DS=covid".parse()?, "n=1".parse()?])?;
+    assert_eq!(
+        vars.substitute(r#"{"dataset":"{{DS}}","n":{{n}},"x":{{ y }}}"#)?,
+        r#"{"dataset":"covid","n":1,"x":{{ y }}}"#
+    );
+    assert!(vars.substitute("{{DS}} {{unknown}}").is_err());
+    // Without any `--var`, placeholders are still checked
+    let no_vars = TemplateVars::new(vec![])?;
+    assert!(no_vars.substitute("{{DATASET}}").is_err());
+    assert_eq!(no_vars.substitute("{{LINE}} {{ y }}")?, "{{LINE}} {{ y }}");
+    assert!(vars.substitute("{{DS:1}}").is_err());
+    assert!(vars.substitute("{{RANDOM_INT:5:1}}").is_err());
+    assert!("1x=2".parse::<VarAssignment>().is_err());
+    assert!("REP=2".parse::<VarAssignment>().is_err());
+    let s = vars.substitute("{{DS}}:{{LINE}}:{{REP}}:{{RANDOM_INT:-3:-3}}")?;
+    assert_eq!(s, "covid:{{LINE}}:{{REP}}:{{RANDOM_INT:-3:-3}}");
+    assert!(has_builtins(&s));
+    assert!(has_random_builtins(&s));
+    let reference = QueryReferenceWithRepetition {
+        query_reference: QueryReference { query_index: 4 },
+        repetition: 2,
+    };
+    let mut rng = rand::thread_rng();
+    assert_eq!(substitute_builtins(&s, reference, &mut rng), "covid:5:2:-3");
+    assert_eq!(
+        substitute_builtins("{{UUID}}", reference, &mut rng).len(),
+        36
+    );
+    Ok(())
+}
@@ -5,10 +5,10 @@ use std::{
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use anyhow::{anyhow, Context};
-use chrono::{DateTime, Local};
+use anyhow::{anyhow, bail, Context};
+use chrono::{DateTime, Local, SecondsFormat, Utc};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct UnixTimeWrap(pub SystemTime);
 
 impl Display for UnixTimeWrap {
@@ -37,16 +37,71 @@ impl FromStr for UnixTimeWrap {
     }
 }
 
-pub fn system_time_to_rfc3339(t: SystemTime) -> String {
-    let t: DateTime<Local> = DateTime::from(t);
-    t.to_rfc3339()
+/// The time zone to show RFC 3339 timestamps in, as given to
+/// `--timestamps`
+#[derive(Debug, Clone, Copy, Default, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimestampZone {
+    /// The local time zone of the machine
+    #[default]
+    Local,
+    /// UTC (shown with a `Z` suffix)
+    Utc,
 }
 
-pub struct Rfc3339TimeWrap(pub SystemTime);
+impl FromStr for TimestampZone {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "local" => Ok(Self::Local),
+            "utc" => Ok(Self::Utc),
+            _ => bail!("expecting `local` or `utc`, got {s:?}"),
+        }
+    }
+}
+
+pub fn system_time_to_rfc3339(t: SystemTime, zone: TimestampZone) -> String {
+    match zone {
+        TimestampZone::Local => DateTime::<Local>::from(t).to_rfc3339(),
+        TimestampZone::Utc => DateTime::<Utc>::from(t).to_rfc3339_opts(SecondsFormat::AutoSi, true),
+    }
+}
+
+pub struct Rfc3339TimeWrap(pub SystemTime, pub TimestampZone);
 
 impl Display for Rfc3339TimeWrap {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str(&system_time_to_rfc3339(self.0))
+        f.write_str(&system_time_to_rfc3339(self.0, self.1))
+    }
+}
+
+/// Shows a duration in a human-readable way, with a precision
+/// depending on its size, e.g. `0.35ms`, `456ms`, `1.23s`, `2m03s`,
+/// `1h02m03s`.
+#[derive(Debug, Clone, Copy)]
+pub struct HumanDuration(pub Duration);
+
+impl Display for HumanDuration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let d = self.0;
+        let secs = d.as_secs();
+        if secs >= 3600 {
+            let (h, m, s) = (secs / 3600, secs / 60 % 60, secs % 60);
+            write!(f, "{h}h{m:02}m{s:02}s")
+        } else if secs >= 60 {
+            let (m, s) = (secs / 60, secs % 60);
+            write!(f, "{m}m{s:02}s")
+        } else if secs >= 1 {
+            write!(f, "{:.2}s", d.as_secs_f64())
+        } else {
+            let ms = d.as_secs_f64() * 1000.;
+            if ms >= 10. {
+                write!(f, "{ms:.0}ms")
+            } else {
+                write!(f, "{ms:.2}ms")
+            }
+        }
     }
 }
 
@@ -57,3 +112,13 @@ impl Deref for UnixTimeWrap {
         &self.0
     }
 }
+
+#[test]
+fn t_human_duration() {
+    let t = |secs: f64| HumanDuration(Duration::from_secs_f64(secs)).to_string();
+    assert_eq!(t(0.00035), "0.35ms");
+    assert_eq!(t(0.456), "456ms");
+    assert_eq!(t(1.234), "1.23s");
+    assert_eq!(t(123.4), "2m03s");
+    assert_eq!(t(3723.), "1h02m03s");
+}
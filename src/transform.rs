@@ -0,0 +1,130 @@
+//! Piping request bodies through an external program
+//! (`--transform-command`), e.g. for signing or encrypting them.
+//!
+//! The program is started once (via `sh -c`) and kept running. For
+//! each request, the body is written to its stdin as the length in
+//! bytes (decimal ASCII) followed by `\n`, then the body bytes. The
+//! program has to answer on its stdout in the same form with the
+//! body to send instead. Exchanges happen one at a time, in order.
+//! If the program exits or answers with something else, the request
+//! fails, and so do all further requests, as the state of the
+//! exchange is then unknown.
+
+use std::process::Stdio;
+
+use anyhow::{anyhow, bail, Context, Result};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    process::{Child, ChildStdin, ChildStdout, Command},
+    sync::Mutex,
+};
+
+/// Limit for the lengths announced by the program, to not allocate
+/// absurd amounts of memory when reading garbage
+const MAX_BODY_LEN: usize = 1 << 30;
+
+struct Process {
+    // Kept to kill the program when dropped
+    _child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+pub struct TransformCommand {
+    command: String,
+    /// `None` after a failed exchange
+    process: Mutex<Option<Process>>,
+}
+
+impl TransformCommand {
+    /// Start `command` (a shell command line).
+    pub fn spawn(command: &str) -> Result<Self> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| anyhow!("starting --transform-command {command:?}"))?;
+        let stdin = child.stdin.take().expect("was piped");
+        let stdout = BufReader::new(child.stdout.take().expect("was piped"));
+        Ok(Self {
+            command: command.into(),
+            process: Mutex::new(Some(Process {
+                _child: child,
+                stdin,
+                stdout,
+            })),
+        })
+    }
+
+    /// Send `body` to the program, and return its answer.
+    pub async fn transform(&self, body: &[u8]) -> Result<Vec<u8>> {
+        let mut process = self.process.lock().await;
+        let Some(running) = &mut *process else {
+            bail!(
+                "--transform-command {:?} failed earlier, not sending further requests",
+                self.command
+            )
+        };
+        let result = exchange(running, body).await;
+        if result.is_err() {
+            *process = None;
+        }
+        result.with_context(|| anyhow!("running --transform-command {:?}", self.command))
+    }
+}
+
+async fn exchange(process: &mut Process, body: &[u8]) -> Result<Vec<u8>> {
+    let Process { stdin, stdout, .. } = process;
+    stdin
+        .write_all(format!("{}\n", body.len()).as_bytes())
+        .await
+        .context("writing to the program")?;
+    stdin
+        .write_all(body)
+        .await
+        .context("writing to the program")?;
+    stdin.flush().await.context("writing to the program")?;
+
+    let mut line = String::new();
+    let n = stdout
+        .read_line(&mut line)
+        .await
+        .context("reading from the program")?;
+    if n == 0 {
+        bail!("the program exited (or closed its stdout)")
+    }
+    let len = parse_length_line(&line)?;
+    let mut transformed = vec![0; len];
+    stdout
+        .read_exact(&mut transformed)
+        .await
+        .with_context(|| anyhow!("reading the {len} announced bytes from the program"))?;
+    Ok(transformed)
+}
+
+/// Parse the length announced by the program, e.g. `"42\n"`.
+fn parse_length_line(line: &str) -> Result<usize> {
+    let digits = line
+        .strip_suffix('\n')
+        .ok_or_else(|| anyhow!("missing newline after the length in {line:?}"))?;
+    let len: usize = digits
+        .parse()
+        .with_context(|| anyhow!("expecting a length in bytes, got {line:?}"))?;
+    if len > MAX_BODY_LEN {
+        bail!("announced length {len} is larger than the limit of {MAX_BODY_LEN} bytes")
+    }
+    Ok(len)
+}
+
+#[test]
+fn t_parse_length_line() {
+    assert_eq!(parse_length_line("42\n").unwrap(), 42);
+    assert_eq!(parse_length_line("0\n").unwrap(), 0);
+    assert!(parse_length_line("42").is_err());
+    assert!(parse_length_line(" 42\n").is_err());
+    assert!(parse_length_line("{\"a\":1}\n").is_err());
+    assert!(parse_length_line("99999999999\n").is_err());
+}
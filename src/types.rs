@@ -1,14 +1,20 @@
 //! Basic types for api-query
 
 use std::{
+    collections::{hash_map::Entry, BTreeMap, HashMap, HashSet},
     convert::{TryFrom, TryInto},
     fmt::Display,
+    fs::File,
+    io::{BufRead, BufReader, Read},
     ops::Range,
     path::Path,
     str::FromStr,
+    sync::Arc,
+    time::Duration,
 };
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
+use reqwest::StatusCode;
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Query<'s> {
@@ -66,6 +72,78 @@ fn t_sizes() {
     assert_eq!(size_of::<[QueryReference; 2]>(), 8);
 }
 
+/// The default limit for the size of queries read from files or
+/// stdin (`--max-query-bytes`)
+pub const DEFAULT_MAX_QUERY_BYTES: u64 = 256 * 1024 * 1024;
+
+/// Read `input` to the end, failing if it is larger than `max_bytes`
+/// (instead of running out of memory). `what` describes the input
+/// for error messages.
+pub fn read_to_string_limited(input: impl Read, max_bytes: u64, what: &str) -> Result<String> {
+    let mut s = String::new();
+    input
+        .take(max_bytes.saturating_add(1))
+        .read_to_string(&mut s)
+        .with_context(|| anyhow!("reading {what}"))?;
+    if s.len() as u64 > max_bytes {
+        bail!("{what} is larger than the limit of {max_bytes} bytes")
+    }
+    Ok(s)
+}
+
+/// What separates the queries in a queries file, as given to
+/// `--record-separator`: a string in which `\\0`, `\\n`, `\\t` and
+/// `\\\\` are unescaped. Default: newline.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct RecordSeparator(String);
+
+impl Default for RecordSeparator {
+    fn default() -> Self {
+        Self("\n".into())
+    }
+}
+
+impl FromStr for RecordSeparator {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut separator = String::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            if c == '\\' {
+                separator.push(match chars.next() {
+                    Some('0') => '\0',
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('\\') => '\\',
+                    _ => bail!("invalid escape in record separator {s:?}"),
+                });
+            } else {
+                separator.push(c);
+            }
+        }
+        if separator.is_empty() {
+            bail!("record separator must not be empty")
+        }
+        Ok(Self(separator))
+    }
+}
+
+impl RecordSeparator {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+fn check_num_queries(len: usize) -> Result<()> {
+    (|| -> Option<_> {
+        let maxline: usize = len.checked_add(1)?;
+        let _maxline: u32 = u32::try_from(maxline).ok()?;
+        Some(())
+    })()
+    .ok_or_else(|| anyhow!(">= u32 lines in file"))
+}
+
 #[ouroboros::self_referencing]
 pub struct Queries {
     queries_string: String,
@@ -75,11 +153,13 @@ pub struct Queries {
 }
 
 impl Queries {
-    fn _new(queries_string: String, split: bool) -> Result<Self> {
+    /// Split `queries_string` at `separator` if given, otherwise use
+    /// it as a single query.
+    fn _new(queries_string: String, separator: Option<&RecordSeparator>) -> Result<Self> {
         Self::try_new(queries_string, |queries_string| -> Result<_> {
-            let queries: Vec<Query> = if split {
+            let queries: Vec<Query> = if let Some(separator) = separator {
                 let mut queries: Vec<Query> = queries_string
-                    .split('\n')
+                    .split(separator.as_str())
                     .map(|string| Query { string })
                     .collect();
                 if queries
@@ -96,37 +176,392 @@ impl Queries {
                     string: queries_string,
                 }]
             };
-            (|| -> Option<_> {
-                let maxline: usize = queries.len().checked_add(1)?;
-                let _maxline: u32 = u32::try_from(maxline).ok()?;
-                Some(())
-            })()
-            .ok_or_else(|| anyhow!(">= u32 lines in file"))?;
+            check_num_queries(queries.len())?;
             Ok(queries)
         })
     }
 
+    /// Queries from separate strings (which may contain newlines).
+    pub fn from_strings(strings: impl IntoIterator<Item = impl AsRef<str>>) -> Result<Self> {
+        let mut queries_string = String::new();
+        let mut ends = Vec::new();
+        for string in strings {
+            queries_string.push_str(string.as_ref());
+            ends.push(queries_string.len());
+        }
+        Self::try_new(queries_string, |queries_string| -> Result<_> {
+            check_num_queries(ends.len())?;
+            let mut start = 0;
+            Ok(ends
+                .into_iter()
+                .map(|end| {
+                    let string = &queries_string[start..end];
+                    start = end;
+                    Query { string }
+                })
+                .collect())
+        })
+    }
+
     pub fn from_lines_string(queries_string: String) -> Result<Self> {
-        Self::_new(queries_string, true)
+        Self::_new(queries_string, Some(&RecordSeparator::default()))
     }
 
-    pub fn from_path(path: &Path) -> Result<Self> {
-        let s = std::fs::read_to_string(path)
-            .with_context(|| anyhow!("reading queries file {path:?}"))?;
-        Self::from_lines_string(s)
+    /// Queries separated by `separator` instead of newlines (a
+    /// trailing separator is optional).
+    pub fn from_records_string(
+        queries_string: String,
+        separator: &RecordSeparator,
+    ) -> Result<Self> {
+        Self::_new(queries_string, Some(separator))
+    }
+
+    /// Read the queries file at `path`, which must not be larger
+    /// than `max_bytes`.
+    pub fn from_path(path: &Path, max_bytes: u64, separator: &RecordSeparator) -> Result<Self> {
+        let what = format!("queries file {path:?}");
+        let file = File::open(path).with_context(|| anyhow!("opening {what}"))?;
+        Self::from_records_string(read_to_string_limited(file, max_bytes, &what)?, separator)
+    }
+
+    /// Like `from_path`, but reading stdin if `path` is `-`.
+    pub fn from_path_or_stdin(
+        path: &Path,
+        max_bytes: u64,
+        separator: &RecordSeparator,
+    ) -> Result<Self> {
+        if path == Path::new("-") {
+            Self::from_records_string(
+                read_to_string_limited(std::io::stdin(), max_bytes, "queries from stdin")?,
+                separator,
+            )
+        } else {
+            Self::from_path(path, max_bytes, separator)
+        }
     }
 
     pub fn from_single_query(queries_string: String) -> Result<Self> {
-        Self::_new(queries_string, false)
+        Self::_new(queries_string, None)
     }
 
     fn get_query(&self, i: u32) -> Query<'_> {
         self.borrow_queries()[usize::try_from(i).expect("correct index generation")].clone()
     }
 
+    /// The heap memory used for the queries text and for the index
+    /// into it, in bytes
+    pub fn heap_bytes(&self) -> (usize, usize) {
+        (
+            self.borrow_queries_string().capacity(),
+            self.borrow_queries().capacity() * size_of::<Query>(),
+        )
+    }
+
     pub fn query_index_range(&self) -> Range<usize> {
         0..self.borrow_queries().len()
     }
+
+    /// Split each query of the form `weight\tquery` into its weight
+    /// and the query proper, returning the latter as new `Queries`.
+    pub fn split_weights(&self) -> Result<(Queries, Vec<f64>)> {
+        let mut weights = Vec::new();
+        let mut strings = Vec::new();
+        for (i, query) in self.borrow_queries().iter().enumerate() {
+            let reference = QueryReference {
+                query_index: i as u32,
+            };
+            let (weight, string) = query
+                .string
+                .split_once('\t')
+                .ok_or_else(|| anyhow!("missing tab after weight in line {reference}"))?;
+            let weight: f64 = weight
+                .parse()
+                .with_context(|| anyhow!("parsing weight {weight:?} in line {reference}"))?;
+            if !(weight.is_finite() && weight >= 0.) {
+                bail!("weight {weight} in line {reference} is not a non-negative number")
+            }
+            weights.push(weight);
+            strings.push(string);
+        }
+        Ok((Queries::from_strings(strings)?, weights))
+    }
+
+    /// Split each query of the form `offset_ms\tquery` (`--replay`)
+    /// into its offset from the start of the run and the query
+    /// proper, returning the latter as new `Queries`. The offsets
+    /// must not decrease from one query to the next.
+    pub fn split_replay_offsets(&self) -> Result<(Queries, Vec<Duration>)> {
+        let mut offsets: Vec<Duration> = Vec::new();
+        let mut strings = Vec::new();
+        for (i, query) in self.borrow_queries().iter().enumerate() {
+            let reference = QueryReference {
+                query_index: i as u32,
+            };
+            let (offset_ms, string) = query
+                .string
+                .split_once('\t')
+                .ok_or_else(|| anyhow!("missing tab after offset in line {reference}"))?;
+            let offset_ms: u64 = offset_ms.parse().with_context(|| {
+                anyhow!("parsing offset {offset_ms:?} (milliseconds) in line {reference}")
+            })?;
+            let offset = Duration::from_millis(offset_ms);
+            if let Some(previous) = offsets.last() {
+                if offset < *previous {
+                    bail!(
+                        "offset {offset_ms} in line {reference} is before the offset of \
+                         the previous query ({} ms)",
+                        previous.as_millis()
+                    )
+                }
+            }
+            offsets.push(offset);
+            strings.push(string);
+        }
+        Ok((Queries::from_strings(strings)?, offsets))
+    }
+
+    /// Split off the expected status of each query of the form
+    /// `STATUS\tquery` (`--status-annotations`), returning the
+    /// queries proper as new `Queries`; for queries without such a
+    /// prefix, the expected status is `None`.
+    pub fn split_expected_statuses(&self) -> Result<(Queries, Vec<Option<StatusCode>>)> {
+        let mut statuses = Vec::new();
+        let mut strings = Vec::new();
+        for (i, query) in self.borrow_queries().iter().enumerate() {
+            let (status, string) = split_expected_status(query.string).with_context(|| {
+                let reference = QueryReference {
+                    query_index: i as u32,
+                };
+                anyhow!("in line {reference}")
+            })?;
+            statuses.push(status);
+            strings.push(string);
+        }
+        Ok((Queries::from_strings(strings)?, statuses))
+    }
+
+    /// Split off the endpoint URL of each query of the form
+    /// `URL\tquery` (`--url-annotations`), returning the queries
+    /// proper as new `Queries`; for queries without such a prefix,
+    /// the URL is `None`. Equal URLs share the same allocation.
+    pub fn split_endpoint_urls(&self) -> Result<(Queries, Vec<Option<Arc<str>>>)> {
+        let mut interned = HashMap::<&str, Arc<str>>::new();
+        let mut urls = Vec::new();
+        let mut strings = Vec::new();
+        for query in self.borrow_queries() {
+            let (url, string) = split_endpoint_url(query.string);
+            urls.push(url.map(|url| interned.entry(url).or_insert_with(|| url.into()).clone()));
+            strings.push(string);
+        }
+        Ok((Queries::from_strings(strings)?, urls))
+    }
+
+    /// Split off the ETag of each query of the form `ETAG\tquery`
+    /// (`--etag-annotations`), returning the queries proper as new
+    /// `Queries`; for queries without such a prefix, the ETag is
+    /// `None`.
+    pub fn split_etags(&self) -> Result<(Queries, Vec<Option<Arc<str>>>)> {
+        let mut etags = Vec::new();
+        let mut strings = Vec::new();
+        for query in self.borrow_queries() {
+            let (etag, string) = split_etag(query.string);
+            etags.push(etag.map(Arc::from));
+            strings.push(string);
+        }
+        Ok((Queries::from_strings(strings)?, etags))
+    }
+
+    /// References to the first occurrence of each distinct query
+    /// string, in file order. Duplicate lines are thus represented by
+    /// the line of their first occurrence.
+    pub fn unique_query_references(&self) -> Vec<QueryReference> {
+        let mut seen = HashSet::new();
+        self.borrow_queries()
+            .iter()
+            .enumerate()
+            .filter(|(_, query)| seen.insert(query.string))
+            .map(|(i, _)| QueryReference {
+                query_index: i as u32,
+            })
+            .collect()
+    }
+
+    /// For each line whose query string occurs again later, the
+    /// references to those later lines (the duplicates that
+    /// `unique_query_references` drops), in file order.
+    pub fn duplicate_query_references(&self) -> BTreeMap<QueryReference, Vec<QueryReference>> {
+        let mut first_occurrences = HashMap::new();
+        let mut duplicates = BTreeMap::<QueryReference, Vec<QueryReference>>::new();
+        for (i, query) in self.borrow_queries().iter().enumerate() {
+            let reference = QueryReference {
+                query_index: i as u32,
+            };
+            match first_occurrences.entry(query.string) {
+                Entry::Occupied(first) => {
+                    duplicates.entry(*first.get()).or_default().push(reference)
+                }
+                Entry::Vacant(vacant) => {
+                    vacant.insert(reference);
+                }
+            }
+        }
+        duplicates
+    }
+
+    /// References to the queries that are empty or consist only of
+    /// whitespace (e.g. from blank lines), in file order.
+    pub fn empty_query_references(&self) -> Vec<QueryReference> {
+        self.borrow_queries()
+            .iter()
+            .enumerate()
+            .filter(|(_, query)| query.string.trim().is_empty())
+            .map(|(i, _)| QueryReference {
+                query_index: i as u32,
+            })
+            .collect()
+    }
+}
+
+/// For `--dedup`: the message about the lines skipped as duplicates
+/// (as from `Queries::duplicate_query_references`), if there are any.
+pub fn dedup_notice(duplicates: &BTreeMap<QueryReference, Vec<QueryReference>>) -> Option<String> {
+    let num_duplicates: usize = duplicates.values().map(Vec::len).sum();
+    (num_duplicates > 0).then(|| format!("--dedup: skipping {num_duplicates} duplicate queries"))
+}
+
+/// Split `query` of the form `URL\tquery` (an `http://` or
+/// `https://` URL, then a tab) into the endpoint URL and the query
+/// proper; other queries are returned unchanged.
+pub fn split_endpoint_url(query: &str) -> (Option<&str>, &str) {
+    if let Some((url, string)) = query.split_once('\t') {
+        if url.starts_with("http://") || url.starts_with("https://") {
+            return (Some(url), string);
+        }
+    }
+    (None, query)
+}
+
+/// Split `query` of the form `ETAG\tquery` (an entity tag as in the
+/// `ETag` header, i.e. `"..."` or `W/"..."`, then a tab) into the
+/// ETag and the query proper; other queries are returned unchanged.
+pub fn split_etag(query: &str) -> (Option<&str>, &str) {
+    if let Some((etag, string)) = query.split_once('\t') {
+        let opaque = etag.strip_prefix("W/").unwrap_or(etag);
+        if opaque.len() >= 2
+            && opaque.starts_with('"')
+            && opaque.ends_with('"')
+            && !opaque[1..opaque.len() - 1].contains('"')
+        {
+            return (Some(etag), string);
+        }
+    }
+    (None, query)
+}
+
+/// Split `query` of the form `STATUS\tquery` (3 digits, then a tab)
+/// into the expected status and the query proper; other queries are
+/// returned unchanged.
+pub fn split_expected_status(query: &str) -> Result<(Option<StatusCode>, &str)> {
+    if let Some((status, string)) = query.split_once('\t') {
+        if status.len() == 3 && status.bytes().all(|b| b.is_ascii_digit()) {
+            let status = StatusCode::from_bytes(status.as_bytes())
+                .with_context(|| anyhow!("invalid expected status {status:?}"))?;
+            return Ok((Some(status), string));
+        }
+    }
+    Ok((None, query))
+}
+
+/// Reads a queries file (or stdin) one line at a time, for
+/// `--stream`, instead of keeping all queries in memory like
+/// `Queries`.
+pub struct QueryLines<R: BufRead = Box<dyn BufRead>> {
+    reader: R,
+    what: String,
+    max_bytes: u64,
+    separator: u8,
+    next_index: u32,
+}
+
+impl QueryLines {
+    /// Reading stdin if `path` is `-`. Each line must not be larger
+    /// than `max_bytes`. Only single-byte separators are supported.
+    pub fn from_path_or_stdin(
+        path: &Path,
+        max_bytes: u64,
+        separator: &RecordSeparator,
+    ) -> Result<Self> {
+        let (reader, what): (Box<dyn BufRead>, String) = if path == Path::new("-") {
+            (Box::new(BufReader::new(std::io::stdin())), "stdin".into())
+        } else {
+            let what = format!("queries file {path:?}");
+            let file = File::open(path).with_context(|| anyhow!("opening {what}"))?;
+            (Box::new(BufReader::new(file)), what)
+        };
+        QueryLines::new(reader, what, max_bytes, separator)
+    }
+}
+
+impl<R: BufRead> QueryLines<R> {
+    /// `what` describes the source of `reader` for error messages.
+    pub fn new(
+        reader: R,
+        what: String,
+        max_bytes: u64,
+        separator: &RecordSeparator,
+    ) -> Result<Self> {
+        let separator = match separator.as_str().as_bytes() {
+            [separator] => *separator,
+            _ => bail!("only single-byte record separators are supported when streaming"),
+        };
+        Ok(Self {
+            reader,
+            what,
+            max_bytes,
+            separator,
+            next_index: 0,
+        })
+    }
+}
+
+impl<R: BufRead> Iterator for QueryLines<R> {
+    type Item = Result<(QueryReference, String)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let reference = QueryReference {
+            query_index: self.next_index,
+        };
+        let mut line = Vec::new();
+        match (&mut self.reader)
+            .take(self.max_bytes.saturating_add(2))
+            .read_until(self.separator, &mut line)
+        {
+            Ok(0) => None,
+            Ok(_) => {
+                if line.last() == Some(&self.separator) {
+                    line.pop();
+                }
+                if line.len() as u64 > self.max_bytes {
+                    return Some(Err(anyhow!(
+                        "line {reference} of {} is larger than the limit of {} bytes",
+                        self.what,
+                        self.max_bytes
+                    )));
+                }
+                match self.next_index.checked_add(1) {
+                    Some(next_index) => self.next_index = next_index,
+                    None => return Some(Err(anyhow!(">= u32 lines in {}", self.what))),
+                }
+                match String::from_utf8(line) {
+                    Ok(line) => Some(Ok((reference, line))),
+                    Err(e) => Some(Err(anyhow::Error::from(e)
+                        .context(anyhow!("line {reference} of {} is not UTF-8", self.what)))),
+                }
+            }
+            Err(e) => Some(Err(anyhow::Error::from(e)
+                .context(anyhow!("reading line {reference} of {}", self.what)))),
+        }
+    }
 }
 
 impl QueryReferenceWithRepetition {
@@ -138,13 +573,251 @@ impl QueryReferenceWithRepetition {
     /// file, 0-padded for easy sorting, and the repetition count
     /// (0-based) for that query if a non-1 repetition count was
     /// requested.
-    pub fn output_file_name(&self, show_repetition: bool) -> String {
+    pub fn output_file_name(&self, naming: &OutputFileNaming) -> String {
+        let OutputFileNaming {
+            show_repetition,
+            line_width,
+            repetition_width,
+        } = *naming;
         let line = u64::from(self.query_reference.query_index) + 1;
         if show_repetition {
             let repetition = self.repetition;
-            format!("{line:06}-{repetition:06}")
+            format!("{line:0line_width$}-{repetition:0repetition_width$}")
         } else {
-            format!("{line:06}")
+            format!("{line:0line_width$}")
         }
     }
 }
+
+/// Map the given query references to add their repetition count for
+/// each of them (which also works for endless iterators, like a
+/// cycled list). Needs `queries` just to get the max query id.
+pub fn query_references_with_repetitions<'r, I: IntoIterator<Item = &'r QueryReference>>(
+    queries: &Queries,
+    query_references: I,
+) -> impl Iterator<Item = QueryReferenceWithRepetition> + use<'r, I> {
+    // line0 -> seen, for repetition state
+    let mut query_counters: Vec<u32> = vec![0].repeat(queries.borrow_queries().len());
+
+    query_references
+        .into_iter()
+        .copied()
+        .map(move |query_reference| {
+            let QueryReference { query_index } = query_reference;
+            let i = query_index as usize;
+            let repetition = query_counters[i];
+            query_counters[i] += 1;
+            QueryReferenceWithRepetition {
+                query_reference,
+                repetition,
+            }
+        })
+}
+
+/// The padding widths for `output_file_name`, chosen so that the
+/// names of all files from a run have the same length and thus sort
+/// lexically in numeric order.
+#[derive(Debug, Clone, Copy)]
+pub struct OutputFileNaming {
+    show_repetition: bool,
+    line_width: usize,
+    repetition_width: usize,
+}
+
+impl OutputFileNaming {
+    /// Used for small numbers, too, so that the names stay the same
+    /// as before the width was made variable.
+    const MIN_WIDTH: usize = 6;
+
+    /// `num_queries`: the number of lines in the queries file;
+    /// `max_repetition`: the highest repetition number that will
+    /// occur.
+    pub fn new(show_repetition: bool, num_queries: usize, max_repetition: usize) -> Self {
+        let width = |n: usize| n.to_string().len().max(Self::MIN_WIDTH);
+        Self {
+            show_repetition,
+            line_width: width(num_queries),
+            repetition_width: width(max_repetition),
+        }
+    }
+}
+
+#[test]
+fn t_output_file_name() {
+    let reference = QueryReferenceWithRepetition {
+        query_reference: QueryReference { query_index: 41 },
+        repetition: 3,
+    };
+    let name = |naming| reference.output_file_name(&naming);
+    assert_eq!(name(OutputFileNaming::new(false, 100, 0)), "000042");
+    assert_eq!(name(OutputFileNaming::new(true, 100, 9)), "000042-000003");
+    assert_eq!(
+        name(OutputFileNaming::new(true, 12_345_678, 1_000_000)),
+        "00000042-0000003"
+    );
+}
+
+#[test]
+fn t_query_references_with_repetitions() -> Result<()> {
+    let queries = Queries::from_lines_string("a\nb\n".into())?;
+    let references: Vec<QueryReference> = [1, 0, 1, 1]
+        .iter()
+        .map(|&query_index| QueryReference { query_index })
+        .collect();
+    let repetitions: Vec<u32> = query_references_with_repetitions(&queries, &references)
+        .map(|reference| reference.repetition)
+        .collect();
+    assert_eq!(repetitions, [0, 0, 1, 2]);
+    Ok(())
+}
+
+#[test]
+fn t_duplicate_query_references() -> Result<()> {
+    let queries = Queries::from_lines_string("a\nb\na\nc\nb\na\n".into())?;
+    let reference = |query_index| QueryReference { query_index };
+    assert_eq!(
+        queries.unique_query_references(),
+        [reference(0), reference(1), reference(3)]
+    );
+    let duplicates = queries.duplicate_query_references();
+    assert_eq!(duplicates.len(), 2);
+    assert_eq!(duplicates[&reference(0)], [reference(2), reference(5)]);
+    assert_eq!(duplicates[&reference(1)], [reference(4)]);
+    assert_eq!(
+        dedup_notice(&duplicates).as_deref(),
+        Some("--dedup: skipping 3 duplicate queries")
+    );
+    let queries = Queries::from_lines_string("a\nb\n".into())?;
+    assert!(queries.duplicate_query_references().is_empty());
+    assert_eq!(dedup_notice(&queries.duplicate_query_references()), None);
+    Ok(())
+}
+
+#[test]
+fn t_empty_query_references() -> Result<()> {
+    let queries = Queries::from_lines_string("a\n  \nb\n\t\n\n c \n".into())?;
+    let lines: Vec<String> = queries
+        .empty_query_references()
+        .iter()
+        .map(|reference| reference.to_string())
+        .collect();
+    assert_eq!(lines, ["2", "4", "5"]);
+    let queries = Queries::from_lines_string("a\nb\n".into())?;
+    assert!(queries.empty_query_references().is_empty());
+    Ok(())
+}
+
+#[test]
+fn t_record_separator() -> Result<()> {
+    let separator: RecordSeparator = r"\0".parse()?;
+    let queries = Queries::from_records_string("a\nb\0c\0".into(), &separator)?;
+    let strings: Vec<&str> = queries.borrow_queries().iter().map(|q| q.string).collect();
+    assert_eq!(strings, ["a\nb", "c"]);
+    assert_eq!(r"--\\".parse::<RecordSeparator>()?.as_str(), "--\\");
+    assert!(r"\x".parse::<RecordSeparator>().is_err());
+    assert!("".parse::<RecordSeparator>().is_err());
+    Ok(())
+}
+
+#[test]
+fn t_query_lines() -> Result<()> {
+    let lines = |input: &'static [u8], max_bytes, separator: &str| -> Result<Vec<String>> {
+        QueryLines::new(input, "test".into(), max_bytes, &separator.parse()?)?
+            .map(|line| line.map(|(_, line)| line))
+            .collect()
+    };
+    let err = |input, max_bytes| format!("{:#}", lines(input, max_bytes, r"\n").unwrap_err());
+
+    assert_eq!(lines(b"a\n\nbc", 10, r"\n")?, ["a", "", "bc"]);
+    assert_eq!(lines(b"a\0b\nc\0", 10, r"\0")?, ["a", "b\nc"]);
+    // The limit is on the line without its separator
+    assert_eq!(lines(b"abc\nabc", 3, r"\n")?, ["abc", "abc"]);
+    assert_eq!(
+        err(b"abc\nabcd\n", 3),
+        "line 2 of test is larger than the limit of 3 bytes"
+    );
+    // Lines reaching past the `take` of `max_bytes + 2` bytes
+    assert_eq!(
+        err(b"abcdefgh\n", 3),
+        "line 1 of test is larger than the limit of 3 bytes"
+    );
+    assert_eq!(
+        err(b"abcd", 3),
+        "line 1 of test is larger than the limit of 3 bytes"
+    );
+    assert!(err(b"a\n\xff\n", 3).starts_with("line 2 of test is not UTF-8: "));
+    assert_eq!(
+        format!(
+            "{:#}",
+            QueryLines::new(&b""[..], "test".into(), 3, &"--".parse()?)
+                .err()
+                .expect("multi-byte separator")
+        ),
+        "only single-byte record separators are supported when streaming"
+    );
+
+    let mut query_lines = QueryLines::new(&b"a\nb\n"[..], "test".into(), 3, &r"\n".parse()?)?;
+    query_lines.next_index = u32::MAX - 1;
+    let (reference, line) = query_lines.next().expect("a line")?;
+    assert_eq!((reference.query_index, line.as_str()), (u32::MAX - 1, "a"));
+    assert_eq!(
+        format!("{:#}", query_lines.next().expect("an error").unwrap_err()),
+        ">= u32 lines in test"
+    );
+    Ok(())
+}
+
+#[test]
+fn t_split_endpoint_url() {
+    assert_eq!(
+        split_endpoint_url("http://localhost:3000/sample/aggregated\t{}"),
+        (Some("http://localhost:3000/sample/aggregated"), "{}")
+    );
+    assert_eq!(
+        split_endpoint_url("https://x/\t400\t{}"),
+        (Some("https://x/"), "400\t{}")
+    );
+    assert_eq!(split_endpoint_url("400\t{}"), (None, "400\t{}"));
+    assert_eq!(
+        split_endpoint_url("{\"a\": \"http://x\"}"),
+        (None, "{\"a\": \"http://x\"}")
+    );
+}
+
+#[test]
+fn t_split_expected_status() -> Result<()> {
+    assert_eq!(
+        split_expected_status("400\t{\"a\":1}")?,
+        (Some(StatusCode::BAD_REQUEST), "{\"a\":1}")
+    );
+    assert_eq!(split_expected_status("{\"a\":\t1}")?, (None, "{\"a\":\t1}"));
+    assert_eq!(split_expected_status("4000\tx")?, (None, "4000\tx"));
+    assert!(split_expected_status("099\tx").is_err());
+    Ok(())
+}
+
+#[test]
+fn t_split_replay_offsets() -> Result<()> {
+    let queries = Queries::from_lines_string("0\ta\n250\tb\t1\n250\tc\n".into())?;
+    let (queries, offsets) = queries.split_replay_offsets()?;
+    let strings: Vec<&str> = queries.borrow_queries().iter().map(|q| q.string).collect();
+    assert_eq!(strings, ["a", "b\t1", "c"]);
+    assert_eq!(offsets, [0, 250, 250].map(Duration::from_millis));
+    let queries = Queries::from_lines_string("10\ta\n5\tb\n".into())?;
+    assert!(queries.split_replay_offsets().is_err());
+    let queries = Queries::from_lines_string("a\n".into())?;
+    assert!(queries.split_replay_offsets().is_err());
+    Ok(())
+}
+
+#[test]
+fn t_split_etag() {
+    assert_eq!(
+        split_etag("\"abc\"\t{\"a\":1}"),
+        (Some("\"abc\""), "{\"a\":1}")
+    );
+    assert_eq!(split_etag("W/\"1-2\"\tq"), (Some("W/\"1-2\""), "q"));
+    assert_eq!(split_etag("{\"a\":\t1}"), (None, "{\"a\":\t1}"));
+    assert_eq!(split_etag("\"a\"b\"\tq"), (None, "\"a\"b\"\tq"));
+    assert_eq!(split_etag("\"\tq"), (None, "\"\tq"));
+}
@@ -0,0 +1,196 @@
+//! Reading target files in vegeta's "http" format
+//! (`--targets-file`), e.g.
+//!
+//! ```text
+//! POST http://localhost:8080/query
+//! Content-Type: application/json
+//! @bodies/query1.json
+//!
+//! GET http://localhost:8080/other
+//! X-Account-ID: 8675309
+//! ```
+//!
+//! Each target is a line `METHOD URL`, followed by its header lines
+//! `Name: value` (repeated names are all sent), and optionally a
+//! body read from the file after `@` (relative to the current
+//! directory), which has to come last. Targets are separated by
+//! empty lines. Lines starting with `#` are comments. Other
+//! directives are rejected, as dropping them would send different
+//! requests than vegeta.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue},
+    Method,
+};
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct VegetaTarget {
+    pub method: Method,
+    pub url: String,
+    pub headers: HeaderMap,
+    /// The file with the request body, if any (otherwise the body is
+    /// empty)
+    pub body_file: Option<PathBuf>,
+}
+
+impl VegetaTarget {
+    /// The target as a query to be run with `--body-files`: `@PATH`,
+    /// or the empty query without body file.
+    pub fn body_query(&self) -> String {
+        if let Some(body_file) = &self.body_file {
+            format!("@{}", body_file.to_string_lossy())
+        } else {
+            String::new()
+        }
+    }
+}
+
+/// Read and parse the vegeta targets file at `path`.
+pub fn read_vegeta_targets(path: &Path) -> Result<Vec<VegetaTarget>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| anyhow!("reading --targets-file {path:?}"))?;
+    parse_vegeta_targets(&text).with_context(|| anyhow!("in --targets-file {path:?}"))
+}
+
+/// Parse a `METHOD URL` target line, `None` if it isn't one.
+fn parse_target_line(line: &str) -> Option<Result<(Method, &str)>> {
+    let (method, url) = line.split_once(' ')?;
+    if method.is_empty() || !method.bytes().all(|b| b.is_ascii_uppercase()) {
+        return None;
+    }
+    let method = Method::from_bytes(method.as_bytes()).expect("upper case letters are valid");
+    let url = url.trim();
+    if !(url.starts_with("http://") || url.starts_with("https://"))
+        || url.contains(char::is_whitespace)
+    {
+        return Some(Err(anyhow!(
+            "expecting an http:// or https:// URL, got {url:?}"
+        )));
+    }
+    Some(Ok((method, url)))
+}
+
+/// Parse the contents of a vegeta targets file. Errors name the
+/// (1-based) line they are about.
+pub fn parse_vegeta_targets(text: &str) -> Result<Vec<VegetaTarget>> {
+    let mut targets: Vec<VegetaTarget> = Vec::new();
+    // Whether the last target can still get header or body lines
+    let mut in_target = false;
+    for (i, line) in text.lines().enumerate() {
+        let lineno = i + 1;
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() {
+            in_target = false;
+            continue;
+        }
+        if line.starts_with('#') {
+            continue;
+        }
+        let target = targets.last_mut().filter(|_| in_target);
+        if let Some(path) = line.strip_prefix('@') {
+            let Some(target) = target else {
+                bail!("line {lineno}: body file {line:?} without a preceding target line")
+            };
+            if target.body_file.is_some() {
+                bail!("line {lineno}: more than one body file for the same target")
+            }
+            if path.is_empty() {
+                bail!("line {lineno}: missing path after '@'")
+            }
+            target.body_file = Some(path.into());
+            continue;
+        }
+        if let Some(parsed) = parse_target_line(line) {
+            let (method, url) = parsed.with_context(|| anyhow!("line {lineno}"))?;
+            targets.push(VegetaTarget {
+                method,
+                url: url.into(),
+                headers: HeaderMap::new(),
+                body_file: None,
+            });
+            in_target = true;
+            continue;
+        }
+        if let (Some(target), Some((name, value))) = (target, line.split_once(':')) {
+            if target.body_file.is_some() {
+                bail!("line {lineno}: header {line:?} after the body file")
+            }
+            let name = HeaderName::from_bytes(name.trim().as_bytes())
+                .with_context(|| anyhow!("line {lineno}: invalid header name {name:?}"))?;
+            let value = HeaderValue::from_str(value.trim())
+                .with_context(|| anyhow!("line {lineno}: invalid header value {value:?}"))?;
+            target.headers.append(name, value);
+            continue;
+        }
+        bail!("line {lineno}: unsupported directive {line:?}")
+    }
+    Ok(targets)
+}
+
+#[test]
+fn t_parse_vegeta_targets() {
+    let targets = parse_vegeta_targets(
+        "# comment\n\
+         POST http://localhost:8080/query\n\
+         Content-Type: application/json\n\
+         X-Tag: a\n\
+         X-Tag:b\n\
+         @bodies/1.json\n\
+         \n\
+         GET https://example.com/other\r\n",
+    )
+    .unwrap();
+    let mut headers = HeaderMap::new();
+    headers.insert("content-type", "application/json".parse().unwrap());
+    headers.append("x-tag", "a".parse().unwrap());
+    headers.append("x-tag", "b".parse().unwrap());
+    assert_eq!(
+        targets,
+        [
+            VegetaTarget {
+                method: Method::POST,
+                url: "http://localhost:8080/query".into(),
+                headers,
+                body_file: Some("bodies/1.json".into()),
+            },
+            VegetaTarget {
+                method: Method::GET,
+                url: "https://example.com/other".into(),
+                headers: HeaderMap::new(),
+                body_file: None,
+            },
+        ]
+    );
+    assert_eq!(targets[0].body_query(), "@bodies/1.json");
+    assert_eq!(targets[1].body_query(), "");
+
+    let err = |text| format!("{:#}", parse_vegeta_targets(text).unwrap_err());
+    assert_eq!(
+        err("POST http://a/\n@x\nX-Account-ID: 8675309\n"),
+        "line 3: header \"X-Account-ID: 8675309\" after the body file"
+    );
+    assert!(err("POST http://a/\nBad Name: 1\n").starts_with("line 2: invalid header name"));
+    assert_eq!(
+        err("POST http://a/\n\nX-Account-ID: 8675309\n"),
+        "line 3: unsupported directive \"X-Account-ID: 8675309\""
+    );
+    assert_eq!(
+        err("POST http://a/\n@x\n@y\n"),
+        "line 3: more than one body file for the same target"
+    );
+    assert_eq!(
+        err("@x\n"),
+        "line 1: body file \"@x\" without a preceding target line"
+    );
+    assert_eq!(
+        err("POST ftp://a/\n"),
+        "line 1: expecting an http:// or https:// URL, got \"ftp://a/\""
+    );
+    assert_eq!(
+        err("{\"query\": 1}\n"),
+        "line 1: unsupported directive \"{\\\"query\\\": 1}\""
+    );
+}